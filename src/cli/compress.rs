@@ -0,0 +1,123 @@
+use crate::{Base64Format, CmdExecutor};
+
+use super::verify_file;
+use clap::Parser;
+use core::fmt;
+use std::{io::Write, str::FromStr};
+
+#[derive(Debug, Parser)]
+pub struct CompressOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    #[arg(short, long, value_parser = parse_compress_format, default_value = "gzip")]
+    pub format: CompressFormat,
+    #[arg(short, long)]
+    pub level: Option<u32>,
+    #[arg(long, value_parser = parse_text_encoding)]
+    pub encode: Option<Base64Format>,
+}
+
+#[derive(Debug, Parser)]
+pub struct DecompressOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    #[arg(short, long, value_parser = parse_compress_format, default_value = "gzip")]
+    pub format: CompressFormat,
+    #[arg(long, value_parser = parse_text_encoding)]
+    pub decode: Option<Base64Format>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressFormat {
+    Gzip,
+    Zstd,
+    Brotli,
+    /// zlib-wrapped deflate, as used by HTTP `Content-Encoding: deflate`.
+    Deflate,
+}
+
+pub(crate) fn parse_compress_format(format: &str) -> Result<CompressFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_text_encoding(encoding: &str) -> Result<Base64Format, anyhow::Error> {
+    match encoding.to_lowercase().as_str() {
+        "base64" => Ok(Base64Format::Standard),
+        "base64url" => Ok(Base64Format::UrlSafe),
+        _ => Err(anyhow::anyhow!(
+            "Invalid encoding, expected base64 or base64url"
+        )),
+    }
+}
+
+impl FromStr for CompressFormat {
+    type Err = anyhow::Error;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(CompressFormat::Gzip),
+            "zstd" | "zst" => Ok(CompressFormat::Zstd),
+            "brotli" | "br" => Ok(CompressFormat::Brotli),
+            "deflate" => Ok(CompressFormat::Deflate),
+            _ => Err(anyhow::anyhow!("Invalid compress format")),
+        }
+    }
+}
+
+impl From<CompressFormat> for &'static str {
+    fn from(format: CompressFormat) -> Self {
+        match format {
+            CompressFormat::Gzip => "gzip",
+            CompressFormat::Zstd => "zstd",
+            CompressFormat::Brotli => "brotli",
+            CompressFormat::Deflate => "deflate",
+        }
+    }
+}
+
+impl fmt::Display for CompressFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl CmdExecutor for CompressOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = crate::get_reader(&self.input)?;
+        let mut writer = crate::get_writer(&self.output)?;
+        match self.encode {
+            Some(encoding) => {
+                let mut buf = Vec::new();
+                crate::process_compress(&mut reader, &mut buf, self.format, self.level)?;
+                let encoded = crate::process_encode(&mut &buf[..], encoding)?;
+                writeln!(writer, "{}", encoded)?;
+            }
+            None => {
+                crate::process_compress(&mut reader, &mut writer, self.format, self.level)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for DecompressOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = crate::get_reader(&self.input)?;
+        let mut writer = crate::get_writer(&self.output)?;
+        match self.decode {
+            Some(encoding) => {
+                let buf = crate::process_decode(&mut reader, encoding)?;
+                crate::process_decompress(&mut &buf[..], &mut writer, self.format)?;
+            }
+            None => {
+                crate::process_decompress(&mut reader, &mut writer, self.format)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use core::fmt;
+use std::str::FromStr;
 
-use crate::CmdExecutor;
+use super::passwd::parse_hash_algorithm;
+use crate::{CharClasses, CmdExecutor, PasswordHashAlgorithm, PasswordHashParams};
 use zxcvbn::zxcvbn;
 
 #[derive(Debug, Parser)]
@@ -8,32 +11,180 @@ pub struct GenPassOpts {
     #[arg(short, long, default_value_t = 16)]
     pub length: u8,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-uppercase", action = ArgAction::SetFalse, help = "Exclude uppercase letters")]
     pub uppercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-lowercase", action = ArgAction::SetFalse, help = "Exclude lowercase letters")]
     pub lowercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-number", action = ArgAction::SetFalse, help = "Exclude numbers")]
     pub number: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-symbol", action = ArgAction::SetFalse, help = "Exclude symbols")]
     pub symbol: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["uppercase", "lowercase", "number", "symbol"],
+        help = "Custom character set, repeat to require one character from each set"
+    )]
+    pub charset: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "Characters to never use, e.g. \"O0Il1\""
+    )]
+    pub exclude: String,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["charset", "exclude", "length", "uppercase", "lowercase", "number", "symbol"],
+        help = "Generate a passphrase of this many words from the EFF long wordlist"
+    )]
+    pub words: Option<u8>,
+
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+
+    #[arg(
+        long,
+        requires = "words",
+        help = "Capitalize each word of the passphrase"
+    )]
+    pub capitalize: bool,
+
+    #[arg(long, requires = "words", help = "Append a random digit to one word")]
+    pub digit: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["charset", "words", "length", "uppercase", "lowercase", "number", "symbol"],
+        help = "Generate from a pattern, e.g. \"Cvccvc-99-!\" (A/a letter, C/c consonant, V/v vowel, 9 number, ! symbol, * any, \\ escape)"
+    )]
+    pub pattern: Option<String>,
+
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub count: u32,
+
+    #[arg(long, default_value = "plain", value_parser = parse_genpass_format)]
+    pub format: GenPassFormat,
+
+    #[arg(
+        long,
+        value_parser = parse_hash_algorithm,
+        help = "Also output a hash of each password (argon2id, bcrypt, scrypt or pbkdf2)"
+    )]
+    pub hash: Option<PasswordHashAlgorithm>,
+}
+
+/// How passwords are generated; character classes only apply to the default mode.
+enum GenPassMode<'a> {
+    Phrase(u8),
+    Pattern(&'a str),
+    Charset(CharClasses),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GenPassFormat {
+    Plain,
+    Json,
+    Csv,
+}
+
+impl GenPassOpts {
+    pub fn char_classes(&self) -> anyhow::Result<CharClasses> {
+        let classes = if self.charset.is_empty() {
+            CharClasses::new(self.uppercase, self.lowercase, self.number, self.symbol)
+        } else {
+            CharClasses::custom(&self.charset)
+        };
+        classes.exclude(&self.exclude)
+    }
+
+    fn mode(&self) -> anyhow::Result<GenPassMode<'_>> {
+        Ok(match (self.words, &self.pattern) {
+            (Some(words), _) => GenPassMode::Phrase(words),
+            (None, Some(pattern)) => GenPassMode::Pattern(pattern),
+            (None, None) => GenPassMode::Charset(self.char_classes()?),
+        })
+    }
 }
 
 impl CmdExecutor for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let password = crate::process_genpass(
-            self.length,
-            self.uppercase,
-            self.lowercase,
-            self.number,
-            self.symbol,
-        )?;
-        println!("{}", password);
-        // output password strength in stderr
-        let estimate = zxcvbn(&password, &[])?;
-        eprintln!("Password strength: {}", estimate.score());
+        let mode = self.mode()?;
+        let mut passwords = Vec::with_capacity(self.count as usize);
+        let mut entropy = None;
+        for _ in 0..self.count {
+            let password = match &mode {
+                GenPassMode::Phrase(words) => {
+                    let (phrase, bits) = crate::process_genphrase(
+                        *words,
+                        &self.separator,
+                        self.capitalize,
+                        self.digit,
+                    )?;
+                    entropy = Some(bits);
+                    phrase
+                }
+                GenPassMode::Pattern(pattern) => crate::process_genpattern(pattern, &self.exclude)?,
+                GenPassMode::Charset(classes) => crate::process_genpass(self.length, classes)?,
+            };
+            passwords.push(password);
+        }
+        let hashes = match self.hash {
+            Some(algorithm) => passwords
+                .iter()
+                .map(|p| crate::process_passwd_hash(p, algorithm, &PasswordHashParams::default()))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        println!(
+            "{}",
+            crate::process_genpass_output(&passwords, &hashes, self.format)?
+        );
+
+        if let [password] = passwords.as_slice() {
+            // output password strength in stderr
+            let estimate = zxcvbn(password, &[])?;
+            eprintln!("Password strength: {}", estimate.score());
+            if let Some(entropy) = entropy {
+                eprintln!("Entropy: {:.1} bits", entropy);
+            }
+        }
         Ok(())
     }
 }
+
+fn parse_genpass_format(format: &str) -> Result<GenPassFormat, anyhow::Error> {
+    format.parse()
+}
+
+impl FromStr for GenPassFormat {
+    type Err = anyhow::Error;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "plain" => Ok(GenPassFormat::Plain),
+            "json" => Ok(GenPassFormat::Json),
+            "csv" => Ok(GenPassFormat::Csv),
+            _ => Err(anyhow::anyhow!("Invalid genpass output format")),
+        }
+    }
+}
+
+impl From<GenPassFormat> for &'static str {
+    fn from(format: GenPassFormat) -> Self {
+        match format {
+            GenPassFormat::Plain => "plain",
+            GenPassFormat::Json => "json",
+            GenPassFormat::Csv => "csv",
+        }
+    }
+}
+
+impl fmt::Display for GenPassFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
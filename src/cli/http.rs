@@ -1,8 +1,8 @@
 use std::path::PathBuf;
 
-use crate::CmdExecutor;
+use crate::{CmdExecutor, CompressFormat};
 
-use super::verify_path;
+use super::{compress::parse_compress_format, verify_path};
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 
@@ -11,6 +11,8 @@ use enum_dispatch::enum_dispatch;
 pub enum HttpSubCommand {
     #[command(about = "Send a directory over HTTP")]
     Serve(HttpServeOpts),
+    #[command(about = "Write precompressed siblings for files served by `http serve`")]
+    Precompress(HttpPrecompressOpts),
 }
 
 #[derive(Debug, Parser)]
@@ -21,8 +23,36 @@ pub struct HttpServeOpts {
     pub port: u16,
 }
 
+#[derive(Debug, Parser)]
+pub struct HttpPrecompressOpts {
+    #[arg(value_parser = verify_path, default_value = ".")]
+    pub dir: PathBuf,
+    #[arg(short, long, value_parser = parse_compress_format, value_delimiter = ',', default_value = "gzip,brotli,zstd,deflate")]
+    pub format: Vec<CompressFormat>,
+    #[arg(
+        long,
+        default_value_t = 256,
+        help = "Skip files smaller than this many bytes"
+    )]
+    pub min_size: u64,
+    #[arg(long, help = "Recompress files even if their siblings are up to date")]
+    pub force: bool,
+}
+
 impl CmdExecutor for HttpServeOpts {
     async fn execute(self) -> anyhow::Result<()> {
         crate::process_http_serve(self.dir, self.port).await
     }
 }
+
+impl CmdExecutor for HttpPrecompressOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let stats =
+            crate::process_http_precompress(&self.dir, &self.format, self.min_size, self.force)?;
+        println!(
+            "{} compressed, {} unchanged, {} skipped",
+            stats.compressed, stats.unchanged, stats.skipped
+        );
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use super::report_verification;
 use crate::CmdExecutor;
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
@@ -25,6 +26,8 @@ pub struct JwtSignOpts {
 pub struct JwtVerifyOpts {
     #[arg(short, long)]
     pub token: String,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
 }
 
 impl CmdExecutor for JwtSignOpts {
@@ -37,8 +40,24 @@ impl CmdExecutor for JwtSignOpts {
 
 impl CmdExecutor for JwtVerifyOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let claims = crate::process_jwt_verify(&self.token)?;
-        println!("{:?}", claims);
-        Ok(())
+        match crate::process_jwt_verify(&self.token) {
+            Ok(claims) => {
+                let details = serde_json::json!({ "claims": claims });
+                report_verification("Token", true, self.json, details)?;
+                if !self.json {
+                    println!("{:?}", claims);
+                }
+                Ok(())
+            }
+            // a malformed, forged or expired token is a failed verification
+            Err(e) if e.is::<jsonwebtoken::errors::Error>() => {
+                if !self.json {
+                    eprintln!("{}", e);
+                }
+                let details = serde_json::json!({ "error": e.to_string() });
+                report_verification("Token", false, self.json, details)
+            }
+            Err(e) => Err(e),
+        }
     }
 }
@@ -1,16 +1,26 @@
 mod base64;
+mod compress;
 mod csv;
 mod genpass;
 mod http;
 mod jwt;
+mod otp;
+mod passwd;
 mod text;
 
-use std::path::{Path, PathBuf};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::CryptoError;
 
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 
-pub use self::{base64::*, csv::*, genpass::*, http::*, jwt::*, text::*};
+pub use self::{
+    base64::*, compress::*, csv::*, genpass::*, http::*, jwt::*, otp::*, passwd::*, text::*,
+};
 
 #[derive(Debug, Parser)]
 #[command(name= "rcli", version, author, about, long_about = None)]
@@ -26,6 +36,14 @@ pub enum SubCommand {
     Csv(CsvOpts),
     #[command(name = "genpass", about = "Generate a random password")]
     GenPass(GenPassOpts),
+    #[command(name = "compress", about = "Compress a file or stdin")]
+    Compress(CompressOpts),
+    #[command(name = "decompress", about = "Decompress a file or stdin")]
+    Decompress(DecompressOpts),
+    #[command(subcommand, about = "Generate or verify TOTP/HOTP one-time passwords")]
+    Otp(OtpSubCommand),
+    #[command(subcommand, about = "Check, hash or verify passwords")]
+    Passwd(PasswdSubCommand),
     #[command(subcommand, about = "Encode or decode base64")]
     Base64(Base64SubCommand),
     #[command(subcommand, about = "Text sign/verify")]
@@ -45,6 +63,44 @@ fn verify_file(filename: &str) -> Result<String, &'static str> {
     }
 }
 
+/// Report the outcome of a verify or check command. With `json` the result is
+/// printed as one object, `details` next to `verified`. A failed verification
+/// is returned as an error, so rcli exits with status 1.
+fn report_verification(
+    subject: &str,
+    verified: bool,
+    json: bool,
+    details: serde_json::Value,
+) -> anyhow::Result<()> {
+    if json {
+        let mut report = serde_json::Map::new();
+        report.insert("verified".into(), verified.into());
+        if let serde_json::Value::Object(details) = details {
+            report.extend(details);
+        }
+        println!("{}", serde_json::Value::Object(report));
+    } else if verified {
+        println!("{} verified", subject);
+    }
+    if !verified {
+        return Err(CryptoError::VerificationFailed(format!("{} not verified", subject)).into());
+    }
+    Ok(())
+}
+
+/// Read a password or secret from the first line of `input`, so it never
+/// shows up in argv.
+fn read_secret(input: &str, what: &str) -> anyhow::Result<String> {
+    let mut reader = crate::get_reader(input)?;
+    let mut buf = String::new();
+    reader.read_to_string(&mut buf)?;
+    let secret = buf.lines().next().unwrap_or_default();
+    if secret.is_empty() {
+        anyhow::bail!("No {} provided", what);
+    }
+    Ok(secret.to_string())
+}
+
 fn verify_path(path: &str) -> Result<PathBuf, &'static str> {
     let p = Path::new(path);
     if p.exists() && p.is_dir() {
@@ -65,4 +121,75 @@ mod tests {
         assert_eq!(verify_file("Cargo.toml"), Ok("Cargo.toml".into()));
         assert_eq!(verify_file("not-exist"), Err("File not found"));
     }
+
+    #[test]
+    fn test_genpass_mode_conflicts() {
+        let parse = |args: &[&str]| Opts::try_parse_from([&["rcli", "genpass"], args].concat());
+        assert!(parse(&["--pattern", "aaaa", "--exclude", "0123456789"]).is_ok());
+        assert!(parse(&["--pattern", "aaaa", "--length", "8"]).is_err());
+        assert!(parse(&["--pattern", "aaaa", "--no-symbol"]).is_err());
+        assert!(parse(&["--words", "5", "-l", "8"]).is_err());
+        assert!(parse(&["--words", "5", "--no-number"]).is_err());
+        assert!(parse(&["--length", "8", "--no-number"]).is_ok());
+    }
+
+    #[test]
+    fn test_otp_verify_window_range() {
+        let parse = |window: &str| {
+            Opts::try_parse_from([
+                "rcli", "otp", "verify", "--code", "123456", "--window", window,
+            ])
+        };
+        assert!(parse("10").is_ok());
+        assert!(parse("11").is_err());
+        assert!(parse("4294967295").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_text_open_output_is_private() -> anyhow::Result<()> {
+        use crate::{process_text_key_generate, process_text_seal, CmdExecutor, EncryptionKey};
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let sealed = dir.path().join("sealed");
+        let opened = dir.path().join("opened.txt");
+        process_text_key_generate(
+            TextKeyType::ChaCha20Poly1305,
+            dir.path(),
+            Some("shared"),
+            false,
+            None,
+        )?;
+        let key = dir.path().join("shared.key");
+        let key = key.to_str().unwrap();
+
+        let mut envelope = Vec::new();
+        process_text_seal(
+            &mut &b"meet at noon"[..],
+            &mut envelope,
+            "fixtures/ed25519.sk",
+            &EncryptionKey::KeyFile(key),
+            TextCipher::ChaCha20Poly1305,
+            false,
+        )?;
+        std::fs::write(&sealed, envelope)?;
+
+        let opts = Opts::try_parse_from([
+            "rcli",
+            "text",
+            "open",
+            "--key",
+            key,
+            "--input",
+            sealed.to_str().unwrap(),
+            "--output",
+            opened.to_str().unwrap(),
+        ])?;
+        opts.cmd.execute().await?;
+        assert_eq!(std::fs::read(&opened)?, b"meet at noon");
+        let mode = std::fs::metadata(&opened)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
 }
@@ -0,0 +1,254 @@
+use super::{read_secret, report_verification, verify_file};
+use crate::{CmdExecutor, TotpParams};
+
+use clap::{Args, Parser};
+use core::fmt;
+use enum_dispatch::enum_dispatch;
+use std::str::FromStr;
+
+/// Environment variable the secret is read from when no option gives one.
+const OTP_SECRET_ENV: &str = "RCLI_OTP_SECRET";
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum OtpSubCommand {
+    #[command(about = "Generate a time-based one-time password (RFC 6238)")]
+    Totp(OtpTotpOpts),
+    #[command(about = "Generate a counter-based one-time password (RFC 4226)")]
+    Hotp(OtpHotpOpts),
+    #[command(about = "Verify a TOTP code, or a HOTP code when --counter is given")]
+    Verify(OtpVerifyOpts),
+    #[command(about = "Generate a new secret as an otpauth:// URI")]
+    Secret(OtpSecretOpts),
+}
+
+/// Where the base32 secret comes from. `--secret` puts it in argv, where other
+/// users can see it in `ps`, so a file, stdin or the environment is preferred.
+#[derive(Debug, Args)]
+#[group(multiple = false)]
+pub struct OtpSecretInputOpts {
+    #[arg(
+        short,
+        long,
+        help = "Base32 encoded secret, visible to other users in ps, prefer --secret-file or RCLI_OTP_SECRET"
+    )]
+    pub secret: Option<String>,
+    #[arg(
+        long,
+        value_parser = verify_file,
+        help = "Read the base32 secret from the first line of a file, \"-\" for stdin"
+    )]
+    pub secret_file: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct OtpParamsOpts {
+    #[arg(short, long, default_value_t = 6)]
+    pub digits: u32,
+    #[arg(long, default_value_t = 30, help = "TOTP time step in seconds")]
+    pub period: u64,
+    #[arg(long, value_parser = parse_otp_algorithm, default_value = "sha1")]
+    pub algorithm: OtpAlgorithm,
+}
+
+#[derive(Debug, Parser)]
+pub struct OtpTotpOpts {
+    #[command(flatten)]
+    pub secret: OtpSecretInputOpts,
+    #[arg(long, help = "Unix time in seconds, defaults to now")]
+    pub time: Option<u64>,
+    #[command(flatten)]
+    pub params: OtpParamsOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct OtpHotpOpts {
+    #[command(flatten)]
+    pub secret: OtpSecretInputOpts,
+    #[arg(short, long)]
+    pub counter: u64,
+    #[arg(short, long, default_value_t = 6)]
+    pub digits: u32,
+    #[arg(long, value_parser = parse_otp_algorithm, default_value = "sha1")]
+    pub algorithm: OtpAlgorithm,
+}
+
+#[derive(Debug, Parser)]
+pub struct OtpVerifyOpts {
+    #[command(flatten)]
+    pub secret: OtpSecretInputOpts,
+    #[arg(long)]
+    pub code: String,
+    #[arg(short, long, help = "Verify as HOTP starting at this counter")]
+    pub counter: Option<u64>,
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(0..=10),
+        help = "Time steps before/after now (TOTP) or counters ahead (HOTP) to accept"
+    )]
+    pub window: u64,
+    #[arg(long, help = "Unix time in seconds, defaults to now")]
+    pub time: Option<u64>,
+    #[command(flatten)]
+    pub params: OtpParamsOpts,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct OtpSecretOpts {
+    #[arg(long, default_value = "")]
+    pub issuer: String,
+    #[arg(long)]
+    pub account: String,
+    #[arg(short, long, default_value_t = 20, help = "Secret length in bytes")]
+    pub length: usize,
+    #[command(flatten)]
+    pub params: OtpParamsOpts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+fn parse_otp_algorithm(algorithm: &str) -> Result<OtpAlgorithm, anyhow::Error> {
+    algorithm.parse()
+}
+
+impl FromStr for OtpAlgorithm {
+    type Err = anyhow::Error;
+    fn from_str(algorithm: &str) -> Result<Self, Self::Err> {
+        match algorithm.to_lowercase().as_str() {
+            "sha1" => Ok(OtpAlgorithm::Sha1),
+            "sha256" => Ok(OtpAlgorithm::Sha256),
+            "sha512" => Ok(OtpAlgorithm::Sha512),
+            _ => Err(anyhow::anyhow!("Invalid OTP algorithm")),
+        }
+    }
+}
+
+impl From<OtpAlgorithm> for &'static str {
+    fn from(algorithm: OtpAlgorithm) -> Self {
+        match algorithm {
+            OtpAlgorithm::Sha1 => "sha1",
+            OtpAlgorithm::Sha256 => "sha256",
+            OtpAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+impl fmt::Display for OtpAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl OtpSecretInputOpts {
+    /// Decode the secret from `--secret`, `--secret-file` or `RCLI_OTP_SECRET`.
+    pub fn decode(&self) -> anyhow::Result<Vec<u8>> {
+        let secret = match (&self.secret, &self.secret_file) {
+            (Some(secret), _) => secret.clone(),
+            (None, Some(file)) => read_secret(file, "secret")?,
+            (None, None) => std::env::var(OTP_SECRET_ENV).map_err(|_| {
+                anyhow::anyhow!(
+                    "No secret provided, use --secret-file or set {}",
+                    OTP_SECRET_ENV
+                )
+            })?,
+        };
+        crate::decode_otp_secret(&secret)
+    }
+}
+
+impl From<&OtpParamsOpts> for TotpParams {
+    fn from(opts: &OtpParamsOpts) -> Self {
+        TotpParams {
+            digits: opts.digits,
+            period: opts.period,
+            algorithm: opts.algorithm,
+        }
+    }
+}
+
+impl CmdExecutor for OtpTotpOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let secret = self.secret.decode()?;
+        let time = match self.time {
+            Some(time) => time,
+            None => crate::unix_time()?,
+        };
+        let params = TotpParams::from(&self.params);
+        let code = crate::process_totp(&secret, time, &params)?;
+        println!("{}", code);
+        eprintln!(
+            "Valid for {} more seconds",
+            params.period - time % params.period
+        );
+        Ok(())
+    }
+}
+
+impl CmdExecutor for OtpHotpOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let secret = self.secret.decode()?;
+        let code = crate::process_hotp(&secret, self.counter, self.digits, self.algorithm)?;
+        println!("{}", code);
+        Ok(())
+    }
+}
+
+impl CmdExecutor for OtpVerifyOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let secret = self.secret.decode()?;
+        let params = TotpParams::from(&self.params);
+        let (verified, details) = match self.counter {
+            Some(counter) => {
+                let matched = crate::process_hotp_verify(
+                    &secret,
+                    &self.code,
+                    counter,
+                    self.window,
+                    params.digits,
+                    params.algorithm,
+                )?;
+                if let (Some(matched), false) = (matched, self.json) {
+                    eprintln!(
+                        "Matched counter {}, next counter is {}",
+                        matched,
+                        matched + 1
+                    );
+                }
+                (matched.is_some(), serde_json::json!({ "counter": matched }))
+            }
+            None => {
+                let time = match self.time {
+                    Some(time) => time,
+                    None => crate::unix_time()?,
+                };
+                let matched =
+                    crate::process_totp_verify(&secret, &self.code, time, self.window, &params)?;
+                if let (Some(offset), false) = (matched, self.json) {
+                    eprintln!("Matched time step offset {}", offset);
+                }
+                (matched.is_some(), serde_json::json!({ "offset": matched }))
+            }
+        };
+        report_verification("Code", verified, self.json, details)
+    }
+}
+
+impl CmdExecutor for OtpSecretOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let params = TotpParams::from(&self.params);
+        let (secret, uri) =
+            crate::process_otp_secret(self.length, &self.issuer, &self.account, &params)?;
+        println!("{}", uri);
+        eprintln!("Secret: {}", secret);
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{CmdExecutor, CryptoError, PasswordHashParams};
+
+use super::{read_secret, report_verification, verify_file};
+use clap::{Args, Parser};
+use core::fmt;
+use enum_dispatch::enum_dispatch;
+use std::str::FromStr;
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum PasswdSubCommand {
+    #[command(about = "Audit the strength of passwords, one per line")]
+    Check(PasswdCheckOpts),
+    #[command(about = "Hash a password read from the first line of the input")]
+    Hash(PasswdHashOpts),
+    #[command(about = "Verify a password against a PHC or bcrypt hash")]
+    Verify(PasswdVerifyOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct PasswdCheckOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(0..=4),
+        help = "Fail if any password scores below this (0-4)"
+    )]
+    pub min_score: Option<u8>,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct PasswdHashOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(short, long, value_parser = parse_hash_algorithm, default_value = "argon2id")]
+    pub algorithm: PasswordHashAlgorithm,
+    #[command(flatten)]
+    pub params: PasswdHashParamsOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct PasswdHashParamsOpts {
+    #[arg(long, help = "argon2id memory cost in KiB")]
+    pub memory: Option<u32>,
+    #[arg(long, help = "argon2id passes or pbkdf2 rounds")]
+    pub iterations: Option<u32>,
+    #[arg(long, help = "argon2id lanes or scrypt p")]
+    pub parallelism: Option<u32>,
+    #[arg(long, help = "bcrypt cost or scrypt log2(N)")]
+    pub cost: Option<u32>,
+}
+
+#[derive(Debug, Parser)]
+pub struct PasswdVerifyOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(long)]
+    pub hash: String,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashAlgorithm {
+    Argon2id,
+    Bcrypt,
+    Scrypt,
+    Pbkdf2,
+}
+
+pub(crate) fn parse_hash_algorithm(
+    algorithm: &str,
+) -> Result<PasswordHashAlgorithm, anyhow::Error> {
+    algorithm.parse()
+}
+
+impl FromStr for PasswordHashAlgorithm {
+    type Err = anyhow::Error;
+    fn from_str(algorithm: &str) -> Result<Self, Self::Err> {
+        match algorithm.to_lowercase().as_str() {
+            "argon2id" | "argon2" => Ok(PasswordHashAlgorithm::Argon2id),
+            "bcrypt" => Ok(PasswordHashAlgorithm::Bcrypt),
+            "scrypt" => Ok(PasswordHashAlgorithm::Scrypt),
+            "pbkdf2" => Ok(PasswordHashAlgorithm::Pbkdf2),
+            _ => Err(anyhow::anyhow!("Invalid password hash algorithm")),
+        }
+    }
+}
+
+impl From<PasswordHashAlgorithm> for &'static str {
+    fn from(algorithm: PasswordHashAlgorithm) -> Self {
+        match algorithm {
+            PasswordHashAlgorithm::Argon2id => "argon2id",
+            PasswordHashAlgorithm::Bcrypt => "bcrypt",
+            PasswordHashAlgorithm::Scrypt => "scrypt",
+            PasswordHashAlgorithm::Pbkdf2 => "pbkdf2",
+        }
+    }
+}
+
+impl fmt::Display for PasswordHashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<&PasswdHashParamsOpts> for PasswordHashParams {
+    fn from(opts: &PasswdHashParamsOpts) -> Self {
+        PasswordHashParams {
+            memory: opts.memory,
+            iterations: opts.iterations,
+            parallelism: opts.parallelism,
+            cost: opts.cost,
+        }
+    }
+}
+
+impl CmdExecutor for PasswdCheckOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = crate::get_reader(&self.input)?;
+        let reports = crate::process_passwd_check(&mut reader)?;
+        let min_score = self.min_score.unwrap_or(0);
+
+        let failed = reports.iter().filter(|r| r.score < min_score).count();
+        if self.json {
+            let report = serde_json::json!({
+                "verified": failed == 0,
+                "min_score": min_score,
+                "reports": reports,
+            });
+            println!("{}", report);
+        } else {
+            for report in &reports {
+                let status = if report.score < min_score {
+                    "FAIL"
+                } else {
+                    "ok"
+                };
+                println!(
+                    "#{}: {} score {}/4, guesses 10^{:.1}",
+                    report.line, status, report.score, report.guesses_log10
+                );
+                let times = &report.crack_times;
+                println!(
+                    "  crack time: online throttled {}, online {}, offline slow hash {}, offline fast hash {}",
+                    times.online_throttled,
+                    times.online_unthrottled,
+                    times.offline_slow_hash,
+                    times.offline_fast_hash
+                );
+                if let Some(warning) = &report.warning {
+                    println!("  warning: {}", warning);
+                }
+                for suggestion in &report.suggestions {
+                    println!("  suggestion: {}", suggestion);
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(CryptoError::VerificationFailed(format!(
+                "{} of {} passwords scored below {}",
+                failed,
+                reports.len(),
+                min_score
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl CmdExecutor for PasswdHashOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let password = read_secret(&self.input, "password")?;
+        let hash = crate::process_passwd_hash(&password, self.algorithm, &(&self.params).into())?;
+        println!("{}", hash);
+        Ok(())
+    }
+}
+
+impl CmdExecutor for PasswdVerifyOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let password = read_secret(&self.input, "password")?;
+        let verified = crate::process_passwd_verify(&password, &self.hash)?;
+        report_verification("Password", verified, self.json, serde_json::json!({}))
+    }
+}
@@ -1,10 +1,14 @@
-use crate::CmdExecutor;
+use crate::{CmdExecutor, EncryptionKey};
 
-use super::{verify_file, verify_path};
-use clap::Parser;
+use super::{report_verification, verify_file, verify_path};
+use clap::{Args, Parser};
 use core::fmt;
 use enum_dispatch::enum_dispatch;
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::fs;
 
 #[derive(Debug, Parser)]
@@ -14,12 +18,22 @@ pub enum TextSubCommand {
     Sign(TextSignOpts),
     #[command(about = "Verify a signed message.")]
     Verify(TextVerifyOpts),
+    #[command(about = "Hash every file of a directory into a signed manifest.")]
+    SignManifest(TextSignManifestOpts),
+    #[command(about = "Verify a signed manifest against a directory.")]
+    VerifyManifest(TextVerifyManifestOpts),
     #[command(about = "Generate a new key.")]
     Generate(TextKeyGenerateOpts),
+    #[command(subcommand, about = "Manage keys.")]
+    Key(TextKeySubCommand),
     #[command(about = "Encrypt a message.")]
     Encrypt(TextEncryptOpts),
     #[command(about = "Decrypt a message.")]
     Decrypt(TextDecryptOpts),
+    #[command(about = "Sign a message with an ed25519 key, then encrypt it.")]
+    Seal(TextSealOpts),
+    #[command(about = "Decrypt a sealed message and verify its signature.")]
+    Open(TextOpenOpts),
 }
 
 #[derive(Debug, Parser)]
@@ -30,23 +44,145 @@ pub struct TextSignOpts {
     pub input: String,
     #[arg(long, default_value="blake3", value_parser = parse_format)]
     pub format: TextSignFormat,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "",
+        help = "Write the signature to a file instead of stdout, <input>.sig if no path is given"
+    )]
+    pub sig_file: Option<String>,
 }
 
 #[derive(Debug, Parser)]
-pub struct TextKeyGenerateOpts {
+pub struct TextSignManifestOpts {
+    #[arg(value_parser = verify_path)]
+    pub dir: PathBuf,
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: String,
     #[arg(long, default_value="blake3", value_parser = parse_format)]
     pub format: TextSignFormat,
-    #[arg(short, long, value_parser = verify_path)]
+    #[arg(
+        short,
+        long,
+        default_value = "MANIFEST",
+        help = "Manifest path, the signature goes to <output>.sig"
+    )]
     pub output: PathBuf,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Parser)]
+pub struct TextVerifyManifestOpts {
+    #[arg(value_parser = verify_path)]
+    pub dir: PathBuf,
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: String,
+    #[arg(long, default_value="blake3", value_parser = parse_format)]
+    pub format: TextSignFormat,
+    #[arg(short, long, default_value = "MANIFEST")]
+    pub manifest: PathBuf,
+    #[arg(long, help = "Signature file, defaults to <manifest>.sig")]
+    pub sig_file: Option<PathBuf>,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum TextKeySubCommand {
+    #[command(about = "Convert an ed25519 key between raw, PEM, OpenSSH and JWK formats.")]
+    Convert(TextKeyConvertOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct TextKeyConvertOpts {
+    #[arg(short, long, value_parser = verify_file, help = "Key in any supported format, detected automatically")]
+    pub input: String,
+    #[arg(long, value_parser = parse_key_format, default_value = "pem")]
+    pub to: KeyFormat,
+    #[arg(
+        long,
+        help = "Only write the public key, derived from a private key if needed"
+    )]
+    pub public: bool,
+    #[arg(
+        long,
+        help = "Read a raw 32 byte input as a public key rather than a private seed"
+    )]
+    pub raw_public: bool,
+    #[arg(
+        long,
+        help = "Allow exporting a passphrase protected private key in plaintext"
+    )]
+    pub decrypt: bool,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    Raw,
+    Pem,
+    OpenSsh,
+    Jwk,
+}
+
+/// AEAD sealing `text encrypt` chunks, decryption reads it from the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextCipher {
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+#[derive(Debug, Parser)]
+pub struct TextKeyGenerateOpts {
+    #[arg(long, default_value="blake3", value_parser = parse_key_type)]
+    pub format: TextKeyType,
+    #[arg(
+        short,
+        long,
+        default_value = ".",
+        help = "Output directory, created if missing"
+    )]
+    pub output: PathBuf,
+    #[arg(long, help = "File name prefix, defaults to the format name")]
+    pub name: Option<String>,
+    #[arg(long, help = "Overwrite existing key files")]
+    pub force: bool,
+    #[arg(
+        long,
+        help = "Encrypt the private key with a passphrase, read from RCLI_KEY_PASSPHRASE, RCLI_KEY_PASSPHRASE_FD or the terminal"
+    )]
+    pub encrypt: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextSignFormat {
     Blake3,
     Ed25519,
+    P256,
+    Secp256k1,
+    HmacSha256,
+    HmacSha512,
     ChaCha20Poly1305,
 }
 
+/// Keys `text generate` can create: every signing format, plus the keys that
+/// are only used for encryption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextKeyType {
+    Blake3,
+    Ed25519,
+    P256,
+    Secp256k1,
+    HmacSha256,
+    HmacSha512,
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+    Aes256Gcm,
+    X25519,
+}
+
 #[derive(Debug, Parser)]
 pub struct TextVerifyOpts {
     #[arg(short, long, value_parser = verify_file)]
@@ -56,34 +192,152 @@ pub struct TextVerifyOpts {
     #[arg(long, default_value="blake3", value_parser = parse_format)]
     pub format: TextSignFormat,
     #[arg(short, long)]
-    pub sig: String,
+    pub sig: Option<String>,
+    #[arg(
+        long,
+        value_parser = verify_file,
+        conflicts_with = "sig",
+        help = "Read the signature from a file, defaults to <input>.sig when --sig is not given"
+    )]
+    pub sig_file: Option<String>,
+    #[arg(long, help = "Print the result as a JSON object")]
+    pub json: bool,
 }
 
 #[derive(Debug, Parser)]
 pub struct TextEncryptOpts {
-    #[arg(short, long, value_parser = verify_file)]
-    pub key: String,
+    #[command(flatten)]
+    pub key: TextEncryptionKeyOpts,
+    #[arg(
+        short,
+        long,
+        value_parser = verify_file,
+        group = "TextEncryptionKeyOpts",
+        help = "Encrypt to an x25519 public key, can be repeated"
+    )]
+    pub recipient: Vec<String>,
     #[arg(short, long, value_parser = verify_file, default_value = "-")]
     pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    #[arg(long, value_parser = parse_cipher, default_value = "chacha20poly1305")]
+    pub cipher: TextCipher,
+    #[arg(long, help = "Write base64url text, the default")]
+    pub armor: bool,
+    #[arg(long, conflicts_with = "armor", help = "Write the raw binary format")]
+    pub binary: bool,
 }
 
 #[derive(Debug, Parser)]
 pub struct TextDecryptOpts {
-    #[arg(short, long, value_parser = verify_file)]
-    pub key: String,
+    #[command(flatten)]
+    pub key: TextEncryptionKeyOpts,
+    #[arg(
+        long,
+        value_parser = verify_file,
+        group = "TextEncryptionKeyOpts",
+        help = "Decrypt with an x25519 private key"
+    )]
+    pub identity: Option<String>,
+    #[arg(
+        short,
+        long,
+        value_parser = verify_file,
+        default_value = "-",
+        help = "Armored or binary ciphertext, detected automatically"
+    )]
+    pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct TextSealOpts {
+    #[arg(short, long, value_parser = verify_file, help = "ed25519 private key to sign with")]
+    pub signing_key: String,
+    #[command(flatten)]
+    pub key: TextEncryptionKeyOpts,
+    #[arg(
+        short,
+        long,
+        value_parser = verify_file,
+        group = "TextEncryptionKeyOpts",
+        help = "Encrypt to an x25519 public key, can be repeated"
+    )]
+    pub recipient: Vec<String>,
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    #[arg(long, value_parser = parse_cipher, default_value = "chacha20poly1305")]
+    pub cipher: TextCipher,
+    #[arg(long, help = "Write base64url text, the default")]
+    pub armor: bool,
+    #[arg(long, conflicts_with = "armor", help = "Write the raw binary format")]
+    pub binary: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct TextOpenOpts {
+    #[command(flatten)]
+    pub key: TextEncryptionKeyOpts,
+    #[arg(
+        long,
+        value_parser = verify_file,
+        group = "TextEncryptionKeyOpts",
+        help = "Decrypt with an x25519 private key"
+    )]
+    pub identity: Option<String>,
+    #[arg(
+        long,
+        value_parser = verify_file,
+        help = "Only accept messages signed by this ed25519 public key"
+    )]
+    pub signer: Option<String>,
     #[arg(short, long, value_parser = verify_file, default_value = "-")]
     pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct TextEncryptionKeyOpts {
+    #[arg(short, long, value_parser = verify_file, help = "256-bit key file, see `text generate`")]
+    pub key: Option<String>,
+    #[arg(
+        long,
+        help = "Derive the key from a passphrase, read from RCLI_KEY_PASSPHRASE, RCLI_KEY_PASSPHRASE_FD or the terminal"
+    )]
+    pub passphrase: bool,
+    #[arg(long, value_parser = verify_file, help = "Read the passphrase from the first line of a file")]
+    pub passphrase_file: Option<String>,
 }
 
 fn parse_format(s: &str) -> Result<TextSignFormat, String> {
     match s {
         "blake3" => Ok(TextSignFormat::Blake3),
         "ed25519" => Ok(TextSignFormat::Ed25519),
+        "p256" | "ecdsa-p256" | "es256" => Ok(TextSignFormat::P256),
+        "secp256k1" | "es256k" => Ok(TextSignFormat::Secp256k1),
+        "hmac-sha256" | "hs256" => Ok(TextSignFormat::HmacSha256),
+        "hmac-sha512" | "hs512" => Ok(TextSignFormat::HmacSha512),
         "chacha20poly1305" => Ok(TextSignFormat::ChaCha20Poly1305),
         _ => Err(format!("Invalid format: {}", s)),
     }
 }
 
+fn parse_key_type(s: &str) -> Result<TextKeyType, String> {
+    match s {
+        "xchacha20poly1305" => Ok(TextKeyType::XChaCha20Poly1305),
+        "aes256gcm" => Ok(TextKeyType::Aes256Gcm),
+        "x25519" => Ok(TextKeyType::X25519),
+        _ => parse_format(s)
+            .map(Into::into)
+            .map_err(|_| format!("Invalid key type: {}", s)),
+    }
+}
+
 impl FromStr for TextSignFormat {
     type Err = String;
 
@@ -92,17 +346,66 @@ impl FromStr for TextSignFormat {
     }
 }
 
-impl From<TextSignFormat> for String {
-    fn from(f: TextSignFormat) -> String {
-        match f {
-            TextSignFormat::Blake3 => "blake3".to_string(),
-            TextSignFormat::Ed25519 => "ed25519".to_string(),
-            TextSignFormat::ChaCha20Poly1305 => "chacha20poly1305".to_string(),
+impl From<TextSignFormat> for &'static str {
+    fn from(format: TextSignFormat) -> Self {
+        match format {
+            TextSignFormat::Blake3 => "blake3",
+            TextSignFormat::Ed25519 => "ed25519",
+            TextSignFormat::P256 => "p256",
+            TextSignFormat::Secp256k1 => "secp256k1",
+            TextSignFormat::HmacSha256 => "hmac-sha256",
+            TextSignFormat::HmacSha512 => "hmac-sha512",
+            TextSignFormat::ChaCha20Poly1305 => "chacha20poly1305",
         }
     }
 }
 
 impl fmt::Display for TextSignFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for TextKeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key_type(s)
+    }
+}
+
+impl From<TextSignFormat> for TextKeyType {
+    fn from(format: TextSignFormat) -> Self {
+        match format {
+            TextSignFormat::Blake3 => TextKeyType::Blake3,
+            TextSignFormat::Ed25519 => TextKeyType::Ed25519,
+            TextSignFormat::P256 => TextKeyType::P256,
+            TextSignFormat::Secp256k1 => TextKeyType::Secp256k1,
+            TextSignFormat::HmacSha256 => TextKeyType::HmacSha256,
+            TextSignFormat::HmacSha512 => TextKeyType::HmacSha512,
+            TextSignFormat::ChaCha20Poly1305 => TextKeyType::ChaCha20Poly1305,
+        }
+    }
+}
+
+impl From<TextKeyType> for String {
+    fn from(key_type: TextKeyType) -> String {
+        match key_type {
+            TextKeyType::Blake3 => "blake3".to_string(),
+            TextKeyType::Ed25519 => "ed25519".to_string(),
+            TextKeyType::P256 => "p256".to_string(),
+            TextKeyType::Secp256k1 => "secp256k1".to_string(),
+            TextKeyType::HmacSha256 => "hmac-sha256".to_string(),
+            TextKeyType::HmacSha512 => "hmac-sha512".to_string(),
+            TextKeyType::ChaCha20Poly1305 => "chacha20poly1305".to_string(),
+            TextKeyType::XChaCha20Poly1305 => "xchacha20poly1305".to_string(),
+            TextKeyType::Aes256Gcm => "aes256gcm".to_string(),
+            TextKeyType::X25519 => "x25519".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TextKeyType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", String::from(*self))
     }
@@ -111,57 +414,324 @@ impl fmt::Display for TextSignFormat {
 impl CmdExecutor for TextSignOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let signed = crate::process_text_sign(&self.input, &self.key, self.format)?;
-        println!("{}", signed);
+        match self.sig_file {
+            Some(sig_file) => {
+                let sig_file = if sig_file.is_empty() {
+                    if self.input == "-" {
+                        anyhow::bail!("--sig-file needs a path when signing stdin");
+                    }
+                    format!("{}.sig", self.input)
+                } else {
+                    sig_file
+                };
+                fs::write(&sig_file, format!("{}\n", signed)).await?;
+                eprintln!("Signature written to {}", sig_file);
+            }
+            None => println!("{}", signed),
+        }
         Ok(())
     }
 }
 
 impl CmdExecutor for TextVerifyOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let verified = crate::process_text_verify(&self.input, &self.key, self.format, &self.sig)?;
-        if verified {
-            println!("Signature verified");
-        } else {
-            println!("Signature not verified");
-        }
+        let sig = match (self.sig, self.sig_file) {
+            (Some(sig), _) => sig,
+            (None, Some(sig_file)) => fs::read_to_string(sig_file).await?,
+            (None, None) if self.input != "-" => {
+                fs::read_to_string(format!("{}.sig", self.input)).await?
+            }
+            (None, None) => anyhow::bail!("Either --sig or --sig-file is required"),
+        };
+        let verified = crate::process_text_verify(&self.input, &self.key, self.format, sig.trim())?;
+        let details = serde_json::json!({ "format": self.format.to_string(), "input": self.input });
+        report_verification("Signature", verified, self.json, details)
+    }
+}
+
+impl CmdExecutor for TextSignManifestOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let signed =
+            crate::process_text_sign_manifest(&self.dir, &self.key, self.format, &self.output)?;
+        let sig_file = sig_path(&self.output);
+        fs::write(&sig_file, format!("{}\n", signed)).await?;
+        eprintln!(
+            "Manifest written to {}, signature to {}",
+            self.output.display(),
+            sig_file.display()
+        );
         Ok(())
     }
 }
 
-impl CmdExecutor for TextKeyGenerateOpts {
+impl CmdExecutor for TextVerifyManifestOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let key = crate::process_text_generate(self.format)?;
-        match self.format {
-            TextSignFormat::Blake3 => {
-                let name = self.output.join("blake3.key");
-                fs::write(name, &key[0]).await?;
+        let sig_file = self.sig_file.unwrap_or_else(|| sig_path(&self.manifest));
+        let sig = fs::read_to_string(sig_file).await?;
+        let (verified, diff) = crate::process_text_verify_manifest(
+            &self.dir,
+            &self.manifest,
+            &self.key,
+            self.format,
+            sig.trim(),
+        )?;
+        if !verified {
+            // without a valid signature the differences mean nothing
+            let details = serde_json::json!({ "signature_verified": false });
+            return report_verification("Manifest signature", false, self.json, details);
+        }
+        if !self.json {
+            for path in &diff.changed {
+                println!("changed: {}", path);
             }
-            TextSignFormat::Ed25519 => {
-                let name = &self.output;
-                fs::write(name.join("ed25519.sk"), &key[0]).await?;
-                fs::write(name.join("ed25519.pk"), &key[1]).await?;
+            for path in &diff.missing {
+                println!("missing: {}", path);
             }
-            TextSignFormat::ChaCha20Poly1305 => {
-                let name = self.output.join("chacha20poly1305.key");
-                fs::write(name, &key[0]).await?;
+            for path in &diff.extra {
+                println!("extra: {}", path);
             }
+            if !diff.is_empty() {
+                eprintln!("Manifest signature verified, but the directory does not match");
+            }
+        }
+        let mut details = serde_json::to_value(&diff)?;
+        details["signature_verified"] = true.into();
+        report_verification("Manifest", diff.is_empty(), self.json, details)
+    }
+}
+
+fn sig_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    sig.into()
+}
+
+fn parse_key_format(format: &str) -> Result<KeyFormat, anyhow::Error> {
+    format.parse()
+}
+
+impl FromStr for KeyFormat {
+    type Err = anyhow::Error;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "raw" => Ok(KeyFormat::Raw),
+            "pem" => Ok(KeyFormat::Pem),
+            "openssh" | "ssh" => Ok(KeyFormat::OpenSsh),
+            "jwk" => Ok(KeyFormat::Jwk),
+            _ => Err(anyhow::anyhow!("Invalid key format")),
+        }
+    }
+}
+
+impl From<KeyFormat> for &'static str {
+    fn from(format: KeyFormat) -> Self {
+        match format {
+            KeyFormat::Raw => "raw",
+            KeyFormat::Pem => "pem",
+            KeyFormat::OpenSsh => "openssh",
+            KeyFormat::Jwk => "jwk",
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+fn parse_cipher(cipher: &str) -> Result<TextCipher, anyhow::Error> {
+    cipher.parse()
+}
+
+impl FromStr for TextCipher {
+    type Err = anyhow::Error;
+    fn from_str(cipher: &str) -> Result<Self, Self::Err> {
+        match cipher.to_lowercase().as_str() {
+            "chacha20poly1305" => Ok(TextCipher::ChaCha20Poly1305),
+            "xchacha20poly1305" => Ok(TextCipher::XChaCha20Poly1305),
+            "aes256gcm" | "aes-256-gcm" => Ok(TextCipher::Aes256Gcm),
+            _ => Err(anyhow::anyhow!("Invalid cipher")),
+        }
+    }
+}
+
+impl From<TextCipher> for &'static str {
+    fn from(cipher: TextCipher) -> Self {
+        match cipher {
+            TextCipher::ChaCha20Poly1305 => "chacha20poly1305",
+            TextCipher::XChaCha20Poly1305 => "xchacha20poly1305",
+            TextCipher::Aes256Gcm => "aes256gcm",
+        }
+    }
+}
+
+impl fmt::Display for TextCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl CmdExecutor for TextKeyConvertOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let converted = crate::process_text_key_convert(
+            &self.input,
+            self.to,
+            self.public,
+            self.raw_public,
+            self.decrypt,
+        )?;
+        if self.output == "-" {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&converted.key)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        crate::write_key_file(
+            Path::new(&self.output),
+            &converted.key,
+            converted.private,
+            true,
+        )
+    }
+}
+
+impl CmdExecutor for TextKeyGenerateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let passphrase = if self.encrypt {
+            Some(crate::read_passphrase("Enter passphrase: ", true)?)
+        } else {
+            None
+        };
+        let generated = crate::process_text_key_generate(
+            self.format,
+            &self.output,
+            self.name.as_deref(),
+            self.force,
+            passphrase.as_deref(),
+        )?;
+        for file in &generated.files {
+            eprintln!("Key written to {}", file.display());
+        }
+        if let Some(fingerprint) = generated.fingerprint {
+            println!("{}", fingerprint);
         }
         Ok(())
     }
 }
 
+impl TextEncryptionKeyOpts {
+    /// Resolve the key options, asking for the passphrase twice when `confirm` is set.
+    async fn resolve(&self, confirm: bool) -> anyhow::Result<ResolvedKey> {
+        if let Some(file) = &self.passphrase_file {
+            let content = fs::read_to_string(file).await?;
+            let passphrase = content.lines().next().unwrap_or_default();
+            return ResolvedKey::passphrase(passphrase.to_string());
+        }
+        if self.passphrase {
+            let passphrase = crate::read_passphrase("Enter passphrase: ", confirm)?;
+            return ResolvedKey::passphrase(passphrase);
+        }
+        match &self.key {
+            Some(key) => Ok(ResolvedKey::KeyFile(key.clone())),
+            None => anyhow::bail!("Either --key, --passphrase or --passphrase-file is required"),
+        }
+    }
+
+    /// The key to encrypt with, `recipients` replace the options when given.
+    async fn resolve_encrypt(&self, recipients: Vec<String>) -> anyhow::Result<ResolvedKey> {
+        if recipients.is_empty() {
+            self.resolve(true).await
+        } else {
+            Ok(ResolvedKey::Recipients(recipients))
+        }
+    }
+
+    /// The key to decrypt with, `identity` replaces the options when given.
+    async fn resolve_decrypt(&self, identity: Option<String>) -> anyhow::Result<ResolvedKey> {
+        match identity {
+            Some(identity) => Ok(ResolvedKey::Identity(identity)),
+            None => self.resolve(false).await,
+        }
+    }
+}
+
+enum ResolvedKey {
+    KeyFile(String),
+    Passphrase(String),
+    Recipients(Vec<String>),
+    Identity(String),
+}
+
+impl ResolvedKey {
+    fn passphrase(passphrase: String) -> anyhow::Result<Self> {
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase must not be empty");
+        }
+        Ok(Self::Passphrase(passphrase))
+    }
+
+    fn as_key(&self) -> EncryptionKey<'_> {
+        match self {
+            Self::KeyFile(path) => EncryptionKey::KeyFile(path),
+            Self::Passphrase(passphrase) => EncryptionKey::Passphrase(passphrase),
+            Self::Recipients(paths) => EncryptionKey::Recipients(paths),
+            Self::Identity(path) => EncryptionKey::Identity(path),
+        }
+    }
+}
+
 impl CmdExecutor for TextEncryptOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let encrypted = crate::process_text_encrypt(&self.input, &self.key)?;
-        println!("{}", encrypted);
-        Ok(())
+        let key = self.key.resolve_encrypt(self.recipient).await?;
+        let mut reader = crate::get_reader(&self.input)?;
+        crate::with_output(&self.output, |writer| {
+            crate::process_text_encrypt(
+                &mut reader,
+                writer,
+                &key.as_key(),
+                self.cipher,
+                !self.binary,
+            )
+        })
     }
 }
 
 impl CmdExecutor for TextDecryptOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let decrypted = crate::process_text_decrypt(&self.input, &self.key)?;
-        println!("{}", decrypted);
+        let key = self.key.resolve_decrypt(self.identity).await?;
+        let mut reader = crate::get_reader(&self.input)?;
+        crate::with_output(&self.output, |writer| {
+            crate::process_text_decrypt(&mut reader, writer, &key.as_key())
+        })
+    }
+}
+
+impl CmdExecutor for TextSealOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let key = self.key.resolve_encrypt(self.recipient).await?;
+        let mut reader = crate::get_reader(&self.input)?;
+        crate::with_output(&self.output, |writer| {
+            crate::process_text_seal(
+                &mut reader,
+                writer,
+                &self.signing_key,
+                &key.as_key(),
+                self.cipher,
+                !self.binary,
+            )
+        })
+    }
+}
+
+impl CmdExecutor for TextOpenOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let key = self.key.resolve_decrypt(self.identity).await?;
+        let mut reader = crate::get_reader(&self.input)?;
+        let fingerprint = crate::with_output(&self.output, |writer| {
+            crate::process_text_open(&mut reader, writer, &key.as_key(), self.signer.as_deref())
+        })?;
+        eprintln!("Signed by {}", fingerprint);
         Ok(())
     }
 }
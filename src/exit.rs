@@ -0,0 +1,104 @@
+use std::{io, process::ExitCode};
+
+use crate::CryptoError;
+
+/// Exit status of the rcli process, stable so scripts can rely on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    /// The signature, MAC, token, code or password under test did not verify,
+    /// including one too malformed to check, or a ciphertext failed
+    /// authentication.
+    VerificationFailed = 1,
+    /// Bad arguments or any other unusable input, e.g. a wrong length key or a
+    /// malformed ciphertext. clap exits with this status too.
+    Usage = 2,
+    /// Reading or writing a file or stream failed.
+    Io = 3,
+}
+
+impl ExitStatus {
+    /// Classify an error returned by a command, looking through its causes.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<CryptoError>() {
+                return match err {
+                    CryptoError::InvalidSignatureLength { .. }
+                    | CryptoError::MalformedSignature(_)
+                    | CryptoError::AuthenticationFailed
+                    | CryptoError::VerificationFailed(_) => Self::VerificationFailed,
+                    CryptoError::InvalidKeyLength { .. }
+                    | CryptoError::TruncatedCiphertext
+                    | CryptoError::InvalidCiphertext(_) => Self::Usage,
+                };
+            }
+            if cause.is::<jsonwebtoken::errors::Error>() {
+                return Self::VerificationFailed;
+            }
+            // decoders report malformed input as `InvalidData`, that is not an I/O failure
+            if let Some(err) = cause.downcast_ref::<io::Error>() {
+                if err.kind() != io::ErrorKind::InvalidData {
+                    return Self::Io;
+                }
+            }
+        }
+        Self::Usage
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_status_from_error() {
+        let status = |err: anyhow::Error| ExitStatus::from_error(&err);
+        assert_eq!(
+            status(CryptoError::VerificationFailed("Signature not verified".into()).into()),
+            ExitStatus::VerificationFailed
+        );
+        assert_eq!(
+            status(CryptoError::AuthenticationFailed.into()),
+            ExitStatus::VerificationFailed
+        );
+        let key_length = CryptoError::InvalidKeyLength {
+            algorithm: "ed25519",
+            expected: 32,
+            actual: 3,
+        };
+        assert_eq!(status(key_length.into()), ExitStatus::Usage);
+        let missing = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert_eq!(
+            status(anyhow::Error::from(missing).context("reading key")),
+            ExitStatus::Io
+        );
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "stream did not contain UTF-8");
+        assert_eq!(status(invalid.into()), ExitStatus::Usage);
+        assert_eq!(
+            status(CryptoError::InvalidCiphertext("invalid armor").into()),
+            ExitStatus::Usage
+        );
+        assert_eq!(
+            status(CryptoError::TruncatedCiphertext.into()),
+            ExitStatus::Usage
+        );
+        // a signature that can't be decoded fails like one that doesn't match
+        assert_eq!(
+            status(CryptoError::MalformedSignature("ed25519").into()),
+            ExitStatus::VerificationFailed
+        );
+        let sig_length = CryptoError::InvalidSignatureLength {
+            algorithm: "ed25519",
+            expected: 64,
+            actual: 3,
+        };
+        assert_eq!(status(sig_length.into()), ExitStatus::VerificationFailed);
+        assert_eq!(status(anyhow::anyhow!("Invalid format")), ExitStatus::Usage);
+    }
+}
@@ -1,9 +1,11 @@
 mod cli;
+mod exit;
 mod process;
 mod utils;
 
 pub use cli::*;
 use enum_dispatch::enum_dispatch;
+pub use exit::ExitStatus;
 pub use process::*;
 pub use utils::*;
 
@@ -1,10 +1,18 @@
+use std::process::ExitCode;
+
 use clap::Parser;
-use rcli::{CmdExecutor, Opts};
+use rcli::{CmdExecutor, ExitStatus, Opts};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::fmt::init();
+    // usage errors exit with status 2 from here
     let opts = Opts::parse();
-    opts.cmd.execute().await?;
-    Ok(())
+    match opts.cmd.execute().await {
+        Ok(()) => ExitStatus::Success.into(),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitStatus::from_error(&e).into()
+        }
+    }
 }
@@ -0,0 +1,193 @@
+use std::io::{self, Read, Write};
+
+use crate::cli::CompressFormat;
+use anyhow::Result;
+use flate2::{
+    read::{MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_LG_WINDOW: i32 = 22;
+/// Level 20 and up raise the zstd window past the 8 MiB that RFC 9659 allows
+/// for the `zstd` content coding, which browsers refuse to decode.
+const ZSTD_HTTP_MAX_LEVEL: u32 = 19;
+
+impl CompressFormat {
+    /// The level used when none is given on the command line.
+    pub fn default_level(&self) -> u32 {
+        match self {
+            CompressFormat::Gzip | CompressFormat::Deflate => 6,
+            CompressFormat::Zstd => 3,
+            CompressFormat::Brotli => 11,
+        }
+    }
+
+    /// The highest level accepted by the format.
+    pub fn max_level(&self) -> u32 {
+        match self {
+            CompressFormat::Gzip | CompressFormat::Deflate => 9,
+            CompressFormat::Zstd => 22,
+            CompressFormat::Brotli => 11,
+        }
+    }
+
+    /// The highest level whose output every HTTP client can decode.
+    pub fn http_max_level(&self) -> u32 {
+        match self {
+            CompressFormat::Zstd => ZSTD_HTTP_MAX_LEVEL,
+            _ => self.max_level(),
+        }
+    }
+
+    /// File extension used for compressed siblings, e.g. `index.html.gz`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressFormat::Gzip => "gz",
+            CompressFormat::Zstd => "zst",
+            CompressFormat::Brotli => "br",
+            CompressFormat::Deflate => "zz",
+        }
+    }
+}
+
+pub fn process_compress(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: CompressFormat,
+    level: Option<u32>,
+) -> Result<u64> {
+    let level = level.unwrap_or_else(|| format.default_level());
+    if level > format.max_level() {
+        anyhow::bail!(
+            "Invalid level {} for {}, expected 0-{}",
+            level,
+            format,
+            format.max_level()
+        );
+    }
+
+    let written = match format {
+        CompressFormat::Gzip => {
+            let mut encoder = GzEncoder::new(writer, Compression::new(level));
+            let n = io::copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            n
+        }
+        CompressFormat::Deflate => {
+            let mut encoder = ZlibEncoder::new(writer, Compression::new(level));
+            let n = io::copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            n
+        }
+        CompressFormat::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, level as i32)?;
+            let n = io::copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            n
+        }
+        CompressFormat::Brotli => {
+            // CompressorWriter only finishes the stream on drop and swallows
+            // any error from the final write
+            let params = brotli::enc::BrotliEncoderParams {
+                quality: level as i32,
+                lgwin: BROTLI_LG_WINDOW,
+                ..Default::default()
+            };
+            let mut reader = CountingReader {
+                inner: reader,
+                count: 0,
+            };
+            let mut writer = writer;
+            brotli::BrotliCompress(&mut reader, &mut writer, &params)?;
+            reader.count
+        }
+    };
+    Ok(written)
+}
+
+struct CountingReader<'a> {
+    inner: &'a mut dyn Read,
+    count: u64,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+pub fn process_decompress(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: CompressFormat,
+) -> Result<u64> {
+    let written = match format {
+        CompressFormat::Gzip => io::copy(&mut MultiGzDecoder::new(reader), writer)?,
+        CompressFormat::Deflate => io::copy(&mut ZlibDecoder::new(reader), writer)?,
+        CompressFormat::Zstd => io::copy(&mut zstd::Decoder::new(reader)?, writer)?,
+        CompressFormat::Brotli => io::copy(
+            &mut brotli::Decompressor::new(reader, BROTLI_BUFFER_SIZE),
+            writer,
+        )?,
+    };
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_decompress_roundtrip() -> Result<()> {
+        let data = std::fs::read("Cargo.toml")?;
+        for format in [
+            CompressFormat::Gzip,
+            CompressFormat::Zstd,
+            CompressFormat::Brotli,
+            CompressFormat::Deflate,
+        ] {
+            let mut compressed = Vec::new();
+            process_compress(&mut &data[..], &mut compressed, format, None)?;
+            assert!(compressed.len() < data.len());
+
+            let mut decompressed = Vec::new();
+            process_decompress(&mut &compressed[..], &mut decompressed, format)?;
+            assert_eq!(decompressed, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_invalid_level() {
+        let mut out = Vec::new();
+        let ret = process_compress(&mut &b"hello"[..], &mut out, CompressFormat::Gzip, Some(10));
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_compress_reports_write_errors() {
+        struct FullDisk;
+        impl Write for FullDisk {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::StorageFull.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for format in [
+            CompressFormat::Gzip,
+            CompressFormat::Zstd,
+            CompressFormat::Brotli,
+            CompressFormat::Deflate,
+        ] {
+            let ret = process_compress(&mut &b"hello"[..], &mut FullDisk, format, None);
+            assert!(ret.is_err(), "{} swallowed the write error", format);
+        }
+    }
+}
@@ -2,37 +2,10 @@ use std::fs;
 
 use anyhow::Result;
 use csv::Reader;
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::cli::OutputFormat;
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    name: String,
-    position: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8,
-}
-
-/*
-pub fn process_csv_player_only(input: &str, output: &str) -> Result<()> {
-    let mut reader = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    for result in reader.deserialize() {
-        let record: Player = result?;
-        ret.push(record);
-    }
-    let json = serde_json::to_string_pretty(&ret)?;
-    fs::write(output, json)?;
-    Ok(())
-}
-*/
-
 pub fn process_csv(input: &str, output: String, format: OutputFormat) -> Result<()> {
     let mut reader = Reader::from_path(input)?;
     let mut ret = Vec::with_capacity(128);
@@ -0,0 +1,344 @@
+use std::{io::Read, path::Path};
+
+use super::{
+    error::CryptoError,
+    key_encryption::read_key_file,
+    key_format::sha256_fingerprint,
+    text::{invalid_key_length, prehash, KeyGenerator, KeyLoader, TextSign, TextVerify},
+};
+use anyhow::Result;
+use chacha20poly1305::aead::OsRng;
+use p256::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
+use sha2::Sha256;
+
+/// Fixed size `r || s` signature of both curves.
+const SIGNATURE_LEN: usize = 64;
+/// First byte of a DER encoded signature, the `SEQUENCE` of `r` and `s`.
+const DER_SEQUENCE_TAG: u8 = 0x30;
+
+/// ECDSA over NIST P-256 with SHA-256 (ES256), as used by cloud KMS.
+pub struct P256Signer {
+    key: p256::ecdsa::SigningKey,
+}
+
+pub struct P256Verifier {
+    key: p256::ecdsa::VerifyingKey,
+}
+
+/// ECDSA over secp256k1 with SHA-256, signatures are normalized to low-S.
+pub struct Secp256k1Signer {
+    key: k256::ecdsa::SigningKey,
+}
+
+pub struct Secp256k1Verifier {
+    key: k256::ecdsa::VerifyingKey,
+}
+
+impl TextSign for P256Signer {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
+        use p256::ecdsa::{signature::DigestSigner, Signature};
+        let sig: Signature = self.key.try_sign_digest(prehash::<Sha256>(reader)?)?;
+        Ok(sig.to_bytes().to_vec())
+    }
+}
+
+impl TextVerify for P256Verifier {
+    /// Accepts fixed size `r || s` and DER encoded signatures.
+    fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        use p256::ecdsa::{signature::DigestVerifier, Signature};
+        let sig = parse_signature("p256", sig, Signature::from_slice, Signature::from_der)?;
+        let digest = prehash::<Sha256>(&mut reader)?;
+        Ok(self.key.verify_digest(digest, &sig).is_ok())
+    }
+}
+
+impl TextSign for Secp256k1Signer {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
+        use k256::ecdsa::{signature::DigestSigner, Signature};
+        let sig: Signature = self.key.try_sign_digest(prehash::<Sha256>(reader)?)?;
+        Ok(sig.to_bytes().to_vec())
+    }
+}
+
+impl TextVerify for Secp256k1Verifier {
+    /// Accepts fixed size `r || s` and DER encoded signatures. High-S
+    /// signatures, as produced by some KMS, are normalized before checking.
+    fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        use k256::ecdsa::{signature::DigestVerifier, Signature};
+        let sig = parse_signature("secp256k1", sig, Signature::from_slice, Signature::from_der)?;
+        let sig = sig.normalize_s().unwrap_or(sig);
+        let digest = prehash::<Sha256>(&mut reader)?;
+        Ok(self.key.verify_digest(digest, &sig).is_ok())
+    }
+}
+
+impl P256Signer {
+    pub fn new(key: p256::ecdsa::SigningKey) -> Self {
+        Self { key }
+    }
+
+    /// Accepts raw 32 byte scalars, PKCS#8 and SEC1 PEM private keys.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        if let Some(pem) = as_pem(key) {
+            let key = p256::ecdsa::SigningKey::from_pkcs8_pem(pem).or_else(|_| {
+                p256::SecretKey::from_sec1_pem(pem).map(p256::ecdsa::SigningKey::from)
+            })?;
+            return Ok(Self::new(key));
+        }
+        if key.len() != 32 {
+            return Err(invalid_key_length("p256", 32, key.len()).into());
+        }
+        Ok(Self::new(p256::ecdsa::SigningKey::from_slice(key)?))
+    }
+
+    pub fn verifier(&self) -> P256Verifier {
+        P256Verifier::new(*self.key.verifying_key())
+    }
+}
+
+impl P256Verifier {
+    pub fn new(key: p256::ecdsa::VerifyingKey) -> Self {
+        Self { key }
+    }
+
+    /// Accepts SPKI PEM and SEC1 encoded points, compressed or not, as well
+    /// as any private key format the public key is derived from.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        if let Some(pem) = as_pem(key) {
+            if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_pem(pem) {
+                return Ok(Self::new(key));
+            }
+            return Ok(P256Signer::try_new(key)?.verifier());
+        }
+        match key.len() {
+            32 => Ok(P256Signer::try_new(key)?.verifier()),
+            33 | 65 => Ok(Self::new(p256::ecdsa::VerifyingKey::from_sec1_bytes(key)?)),
+            len => Err(invalid_key_length("p256", 65, len).into()),
+        }
+    }
+
+    /// `SHA256:` fingerprint of the SPKI DER public key.
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(sha256_fingerprint(self.key.to_public_key_der()?.as_bytes()))
+    }
+}
+
+impl Secp256k1Signer {
+    pub fn new(key: k256::ecdsa::SigningKey) -> Self {
+        Self { key }
+    }
+
+    /// Accepts raw 32 byte scalars, PKCS#8 and SEC1 PEM private keys.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        if let Some(pem) = as_pem(key) {
+            let key = k256::ecdsa::SigningKey::from_pkcs8_pem(pem).or_else(|_| {
+                k256::SecretKey::from_sec1_pem(pem).map(k256::ecdsa::SigningKey::from)
+            })?;
+            return Ok(Self::new(key));
+        }
+        if key.len() != 32 {
+            return Err(invalid_key_length("secp256k1", 32, key.len()).into());
+        }
+        Ok(Self::new(k256::ecdsa::SigningKey::from_slice(key)?))
+    }
+
+    pub fn verifier(&self) -> Secp256k1Verifier {
+        Secp256k1Verifier::new(*self.key.verifying_key())
+    }
+}
+
+impl Secp256k1Verifier {
+    pub fn new(key: k256::ecdsa::VerifyingKey) -> Self {
+        Self { key }
+    }
+
+    /// Accepts SPKI PEM and SEC1 encoded points, compressed or not, as well
+    /// as any private key format the public key is derived from.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        if let Some(pem) = as_pem(key) {
+            if let Ok(key) = k256::ecdsa::VerifyingKey::from_public_key_pem(pem) {
+                return Ok(Self::new(key));
+            }
+            return Ok(Secp256k1Signer::try_new(key)?.verifier());
+        }
+        match key.len() {
+            32 => Ok(Secp256k1Signer::try_new(key)?.verifier()),
+            33 | 65 => Ok(Self::new(k256::ecdsa::VerifyingKey::from_sec1_bytes(key)?)),
+            len => Err(invalid_key_length("secp256k1", 65, len).into()),
+        }
+    }
+
+    /// `SHA256:` fingerprint of the SPKI DER public key.
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(sha256_fingerprint(self.key.to_public_key_der()?.as_bytes()))
+    }
+}
+
+impl KeyLoader for P256Signer {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+}
+
+impl KeyLoader for P256Verifier {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+}
+
+impl KeyLoader for Secp256k1Signer {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+}
+
+impl KeyLoader for Secp256k1Verifier {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+}
+
+/// PKCS#8 private and SPKI public keys in PEM, the formats KMS tooling exchanges.
+impl KeyGenerator for P256Signer {
+    fn generate() -> Result<Vec<Vec<u8>>> {
+        let sk = p256::ecdsa::SigningKey::random(&mut OsRng);
+        let pk = sk.verifying_key().to_public_key_pem(LineEnding::LF)?;
+        let sk = sk.to_pkcs8_pem(LineEnding::LF)?;
+        Ok(vec![sk.as_bytes().to_vec(), pk.into_bytes()])
+    }
+}
+
+impl KeyGenerator for Secp256k1Signer {
+    fn generate() -> Result<Vec<Vec<u8>>> {
+        let sk = k256::ecdsa::SigningKey::random(&mut OsRng);
+        let pk = sk.verifying_key().to_public_key_pem(LineEnding::LF)?;
+        let sk = sk.to_pkcs8_pem(LineEnding::LF)?;
+        Ok(vec![sk.as_bytes().to_vec(), pk.into_bytes()])
+    }
+}
+
+fn as_pem(key: &[u8]) -> Option<&str> {
+    std::str::from_utf8(key)
+        .ok()
+        .filter(|s| s.trim_start().starts_with("-----BEGIN"))
+}
+
+/// Parse a fixed size `r || s` or a DER signature. Only input that is neither
+/// 64 bytes long nor DER is reported as a wrong length.
+fn parse_signature<S, E>(
+    algorithm: &'static str,
+    sig: &[u8],
+    from_slice: fn(&[u8]) -> Result<S, E>,
+    from_der: fn(&[u8]) -> Result<S, E>,
+) -> Result<S, CryptoError> {
+    let parsed = match sig.len() {
+        SIGNATURE_LEN => from_slice(sig),
+        _ if sig.first() == Some(&DER_SEQUENCE_TAG) => from_der(sig),
+        actual => {
+            return Err(CryptoError::InvalidSignatureLength {
+                algorithm,
+                expected: SIGNATURE_LEN,
+                actual,
+            })
+        }
+    };
+    parsed.map_err(|_| CryptoError::MalformedSignature(algorithm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_p256_rfc6979_vector() -> Result<()> {
+        // RFC 6979 A.2.5, P-256 with SHA-256, message "sample"
+        let sk = hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let signer = P256Signer::try_new(&sk)?;
+        let sig = signer.sign(&mut &b"sample"[..])?;
+        assert_eq!(
+            sig,
+            hex(concat!(
+                "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716",
+                "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8"
+            ))
+        );
+        let verifier = signer.verifier();
+        assert!(verifier.verify(&b"sample"[..], &sig)?);
+        assert!(!verifier.verify(&b"test"[..], &sig)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secp256k1_vector() -> Result<()> {
+        // private key 1, deterministic low-S signature of "Satoshi Nakamoto"
+        let mut sk = [0u8; 32];
+        sk[31] = 1;
+        let signer = Secp256k1Signer::try_new(&sk)?;
+        let sig = signer.sign(&mut &b"Satoshi Nakamoto"[..])?;
+        assert_eq!(
+            sig,
+            hex(concat!(
+                "934b1ea10a4b3c1757e2b0c017d0b6143ce3c9a7e6a4a49860d7a6ab210ee3d8",
+                "2442ce9d2b916064108014783e923ec36b49743e2ffa1c4496f01a512aafd9e5"
+            ))
+        );
+        assert!(signer.verifier().verify(&b"Satoshi Nakamoto"[..], &sig)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ecdsa_generated_keys() -> Result<()> {
+        let keys = P256Signer::generate()?;
+        let signer = P256Signer::try_new(&keys[0])?;
+        let verifier = P256Verifier::try_new(&keys[1])?;
+        let sig = signer.sign(&mut &b"hello"[..])?;
+        assert!(verifier.verify(&b"hello"[..], &sig)?);
+        // KMS returns DER signatures
+        let der = p256::ecdsa::Signature::from_slice(&sig)?.to_der();
+        assert!(verifier.verify(&b"hello"[..], der.as_bytes())?);
+
+        let keys = Secp256k1Signer::generate()?;
+        let signer = Secp256k1Signer::try_new(&keys[0])?;
+        let verifier = Secp256k1Verifier::try_new(&keys[1])?;
+        let sig = signer.sign(&mut &b"hello"[..])?;
+        assert!(verifier.verify(&b"hello"[..], &sig)?);
+        // the high-S twin of a signature verifies too
+        let high = k256::ecdsa::Signature::from_slice(&sig)?;
+        let high = k256::ecdsa::Signature::from_scalars(high.r(), -*high.s())?;
+        assert!(verifier.verify(&b"hello"[..], &high.to_bytes())?);
+
+        // a key of one curve is not a key of the other
+        assert!(P256Verifier::try_new(&keys[1]).is_err());
+        let err = P256Verifier::try_new(&[4u8; 64]).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidKeyLength { actual: 64, .. })
+        ));
+        let err = verifier.verify(&b"hello"[..], &sig[..63]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidSignatureLength { actual: 63, .. })
+        ));
+        // r = 0 has the right length but is not a valid signature
+        let err = verifier.verify(&b"hello"[..], &[0; 64]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::MalformedSignature("secp256k1"))
+        );
+        let err = verifier.verify(&b"hello"[..], &[0x30, 0]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::MalformedSignature("secp256k1"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,871 @@
+use std::{
+    io::{self, Read, Write},
+    ops::Sub,
+};
+
+use super::{
+    error::CryptoError,
+    key_encryption::{decode_kdf_params, derive_key, read_key_file, KDF_PARAMS_LEN},
+    recipient::{load_recipient, wrap_file_key, Stanza, X25519Identity, STANZA_LEN},
+};
+use crate::cli::TextCipher;
+use aes_gcm::Aes256Gcm;
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, read::DecoderReader, write::EncoderWriter};
+use chacha20poly1305::{
+    aead::{
+        generic_array::{typenum::U5, ArrayLength, GenericArray},
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        AeadInPlace, OsRng, Payload,
+    },
+    ChaCha20Poly1305, Key, KeyInit, XChaCha20Poly1305,
+};
+
+/// File magic of the encrypted stream format.
+const MAGIC: &[u8; 4] = b"RENC";
+const VERSION: u8 = 1;
+/// `magic || version || algorithm || chunk size (u32 BE)`, followed by the
+/// nonce prefix of the algorithm and the key slot type.
+const PREFIX_LEN: usize = MAGIC.len() + 1 + 1 + 4;
+/// STREAM BE32 takes 5 bytes of the nonce for the big-endian chunk counter
+/// and the last-chunk flag, the rest is a random prefix.
+const NONCE_OVERHEAD: usize = 5;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+const ALGORITHM_CHACHA20POLY1305: u8 = 0;
+const ALGORITHM_XCHACHA20POLY1305: u8 = 1;
+const ALGORITHM_AES256GCM: u8 = 2;
+
+const SLOT_KEY_FILE: u8 = 0;
+const SLOT_PASSPHRASE: u8 = 1;
+const SLOT_RECIPIENTS: u8 = 2;
+
+/// Where the stream key comes from.
+pub enum EncryptionKey<'a> {
+    /// A 256-bit key file, usable with every cipher.
+    KeyFile(&'a str),
+    /// A passphrase, stretched with argon2id using a random salt.
+    Passphrase(&'a str),
+    /// X25519 public key files to encrypt to, each can decrypt on its own.
+    Recipients(&'a [String]),
+    /// An X25519 private key file to decrypt with.
+    Identity(&'a str),
+}
+
+/// How the stream key was obtained, recorded in the header so decryption
+/// can derive the same key.
+enum KeySlot {
+    KeyFile,
+    Passphrase {
+        salt: [u8; SALT_LEN],
+        params: argon2::Params,
+    },
+    /// A random file key, wrapped once per recipient.
+    Recipients(Vec<Stanza>),
+}
+
+struct Header {
+    algorithm: TextCipher,
+    chunk_size: usize,
+    nonce: Vec<u8>,
+    slot: KeySlot,
+}
+
+/// The AEAD sealing the chunks, all of them take a 256-bit key.
+enum StreamCipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    // the expanded AES key schedule is large
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+/// Encrypt everything from `reader` into `writer` in constant memory.
+///
+/// The output is a header followed by the plaintext split in chunks, each
+/// sealed with `algorithm` under the STREAM BE32 construction: a chunk's
+/// nonce encodes its position and whether it is the last one, so reordered,
+/// dropped or truncated chunks fail to decrypt. The header records the
+/// algorithm and is authenticated as associated data of every chunk. With
+/// `armor` the binary stream is written as a single line of base64url text.
+pub fn process_text_encrypt(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    key: &EncryptionKey,
+    algorithm: TextCipher,
+    armor: bool,
+) -> Result<()> {
+    let (slot, key) = new_key_slot(key)?;
+    let header = Header::new(algorithm, CHUNK_SIZE, slot);
+    write_stream(&header, &key, reader, writer, armor)
+}
+
+/// Like `process_text_encrypt`, but the plaintext is built by `plaintext` from
+/// the encoded header first, so it can commit to the header it is sealed under.
+pub(super) fn encrypt_with_header<F>(
+    writer: &mut dyn Write,
+    key: &EncryptionKey,
+    algorithm: TextCipher,
+    armor: bool,
+    plaintext: F,
+) -> Result<()>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+{
+    let (slot, key) = new_key_slot(key)?;
+    let header = Header::new(algorithm, CHUNK_SIZE, slot);
+    let plaintext = plaintext(&header.encode())?;
+    write_stream(&header, &key, &mut &plaintext[..], writer, armor)
+}
+
+fn write_stream(
+    header: &Header,
+    key: &Key,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    armor: bool,
+) -> Result<()> {
+    let cipher = StreamCipher::new(header.algorithm, key);
+    if !armor {
+        return encrypt_stream(&cipher, header, reader, writer);
+    }
+    let mut encoder = EncoderWriter::new(&mut *writer, &URL_SAFE_NO_PAD);
+    encrypt_stream(&cipher, header, reader, &mut encoder)?;
+    let writer = encoder.finish()?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decrypt a stream produced by `process_text_encrypt`, armored or binary
+/// input and the cipher are detected automatically and the plaintext is
+/// written as-is.
+///
+/// Plaintext is only written once its chunk has been authenticated, but a
+/// truncated stream is only detected at its end: on error, discard whatever
+/// was written so far.
+pub fn process_text_decrypt(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    key: &EncryptionKey,
+) -> Result<()> {
+    decrypt_with_header(reader, writer, key)?;
+    Ok(())
+}
+
+/// Like `process_text_decrypt`, also returning the raw header of the stream.
+pub(super) fn decrypt_with_header(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    key: &EncryptionKey,
+) -> Result<Vec<u8>> {
+    let mut magic = [0u8; MAGIC.len()];
+    let len = read_full(reader, &mut magic)?;
+    let mut reader = (&magic[..len]).chain(reader);
+    if &magic == MAGIC {
+        return decrypt_stream(key, &mut reader, writer);
+    }
+    let mut decoder = DecoderReader::new(SkipWhitespace(reader), &URL_SAFE_NO_PAD);
+    decrypt_stream(key, &mut decoder, writer).map_err(armor_error)
+}
+
+/// The armor decoder reports bad base64 as an `io::Error`, turn it into the
+/// malformed ciphertext it is rather than a failed read.
+fn armor_error(err: anyhow::Error) -> anyhow::Error {
+    let is_decode_error = err
+        .downcast_ref::<io::Error>()
+        .and_then(|e| e.get_ref())
+        .is_some_and(|e| e.is::<base64::DecodeError>());
+    if is_decode_error {
+        CryptoError::InvalidCiphertext("invalid armor").into()
+    } else {
+        err
+    }
+}
+
+/// Set up the key slot for a new stream, returning it with the stream key.
+fn new_key_slot(key: &EncryptionKey) -> Result<(KeySlot, Key)> {
+    match key {
+        EncryptionKey::KeyFile(path) => Ok((KeySlot::KeyFile, load_key_file(path)?)),
+        EncryptionKey::Passphrase(passphrase) => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let params = argon2::Params::default();
+            let key = derive_key(passphrase, &salt, params.clone())?;
+            Ok((KeySlot::Passphrase { salt, params }, key))
+        }
+        EncryptionKey::Recipients(paths) => {
+            if paths.is_empty() || paths.len() > u8::MAX as usize {
+                anyhow::bail!("Between 1 and {} recipients are supported", u8::MAX);
+            }
+            let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+            let stanzas = paths
+                .iter()
+                .map(|path| wrap_file_key(&file_key, &load_recipient(path)?))
+                .collect::<Result<Vec<_>>>()?;
+            Ok((KeySlot::Recipients(stanzas), file_key))
+        }
+        EncryptionKey::Identity(_) => {
+            anyhow::bail!("An identity only decrypts, encrypt to its public key as a recipient")
+        }
+    }
+}
+
+fn load_key_file(path: &str) -> Result<Key> {
+    let key = read_key_file(path)?;
+    if key.len() != 32 {
+        return Err(CryptoError::InvalidKeyLength {
+            algorithm: "stream cipher",
+            expected: 32,
+            actual: key.len(),
+        }
+        .into());
+    }
+    Ok(Key::clone_from_slice(&key))
+}
+
+fn algorithm_id(algorithm: TextCipher) -> u8 {
+    match algorithm {
+        TextCipher::ChaCha20Poly1305 => ALGORITHM_CHACHA20POLY1305,
+        TextCipher::XChaCha20Poly1305 => ALGORITHM_XCHACHA20POLY1305,
+        TextCipher::Aes256Gcm => ALGORITHM_AES256GCM,
+    }
+}
+
+fn algorithm_from_id(id: u8) -> Option<TextCipher> {
+    match id {
+        ALGORITHM_CHACHA20POLY1305 => Some(TextCipher::ChaCha20Poly1305),
+        ALGORITHM_XCHACHA20POLY1305 => Some(TextCipher::XChaCha20Poly1305),
+        ALGORITHM_AES256GCM => Some(TextCipher::Aes256Gcm),
+        _ => None,
+    }
+}
+
+/// Length of the random nonce prefix, 7 bytes for 96-bit nonces and 19 for
+/// the 192-bit XChaCha20 nonce, which is safe to pick at random for any
+/// number of messages under one key.
+fn nonce_prefix_len(algorithm: TextCipher) -> usize {
+    match algorithm {
+        TextCipher::ChaCha20Poly1305 | TextCipher::Aes256Gcm => 12 - NONCE_OVERHEAD,
+        TextCipher::XChaCha20Poly1305 => 24 - NONCE_OVERHEAD,
+    }
+}
+
+impl StreamCipher {
+    fn new(algorithm: TextCipher, key: &Key) -> Self {
+        match algorithm {
+            TextCipher::ChaCha20Poly1305 => Self::ChaCha20Poly1305(ChaCha20Poly1305::new(key)),
+            TextCipher::XChaCha20Poly1305 => Self::XChaCha20Poly1305(XChaCha20Poly1305::new(key)),
+            TextCipher::Aes256Gcm => Self::Aes256Gcm(Box::new(Aes256Gcm::new(key))),
+        }
+    }
+}
+
+impl Header {
+    fn new(algorithm: TextCipher, chunk_size: usize, slot: KeySlot) -> Self {
+        let mut nonce = vec![0u8; nonce_prefix_len(algorithm)];
+        OsRng.fill_bytes(&mut nonce);
+        Self {
+            algorithm,
+            chunk_size,
+            nonce,
+            slot,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(algorithm_id(self.algorithm));
+        header.extend((self.chunk_size as u32).to_be_bytes());
+        header.extend(&self.nonce);
+        match &self.slot {
+            KeySlot::KeyFile => header.push(SLOT_KEY_FILE),
+            KeySlot::Passphrase { salt, params } => {
+                header.push(SLOT_PASSPHRASE);
+                header.extend(salt);
+                header.extend(params.m_cost().to_be_bytes());
+                header.extend(params.t_cost().to_be_bytes());
+                header.extend(params.p_cost().to_be_bytes());
+            }
+            KeySlot::Recipients(stanzas) => {
+                header.push(SLOT_RECIPIENTS);
+                header.push(stanzas.len() as u8);
+                for stanza in stanzas {
+                    header.extend(stanza.ephemeral);
+                    header.extend(stanza.wrapped);
+                }
+            }
+        }
+        header
+    }
+
+    /// Parse a header, returning it together with its raw bytes.
+    fn read(reader: &mut dyn Read) -> Result<(Self, Vec<u8>)> {
+        let mut prefix = [0u8; PREFIX_LEN];
+        let len = read_full(reader, &mut prefix)?;
+        if len < MAGIC.len() || &prefix[..MAGIC.len()] != MAGIC {
+            return Err(CryptoError::InvalidCiphertext("not an rcli encrypted stream").into());
+        }
+        if len < PREFIX_LEN {
+            return Err(CryptoError::TruncatedCiphertext.into());
+        }
+        if prefix[4] != VERSION {
+            return Err(CryptoError::InvalidCiphertext("unsupported version").into());
+        }
+        let algorithm = algorithm_from_id(prefix[5])
+            .ok_or(CryptoError::InvalidCiphertext("unknown algorithm"))?;
+        let chunk_size = u32::from_be_bytes([prefix[6], prefix[7], prefix[8], prefix[9]]) as usize;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(CryptoError::InvalidCiphertext("invalid chunk size").into());
+        }
+        // the nonce prefix and the key slot type
+        let mut rest = vec![0u8; nonce_prefix_len(algorithm) + 1];
+        if read_full(reader, &mut rest)? < rest.len() {
+            return Err(CryptoError::TruncatedCiphertext.into());
+        }
+        let slot_type = rest.pop().unwrap_or_default();
+        let nonce = rest;
+
+        let mut raw = prefix.to_vec();
+        raw.extend(&nonce);
+        raw.push(slot_type);
+        let slot = match slot_type {
+            SLOT_KEY_FILE => KeySlot::KeyFile,
+            SLOT_PASSPHRASE => {
+                let mut data = [0u8; SALT_LEN + KDF_PARAMS_LEN];
+                if read_full(reader, &mut data)? < data.len() {
+                    return Err(CryptoError::TruncatedCiphertext.into());
+                }
+                raw.extend(data);
+                let params = decode_kdf_params(&data[SALT_LEN..])?;
+                let mut salt = [0u8; SALT_LEN];
+                salt.copy_from_slice(&data[..SALT_LEN]);
+                KeySlot::Passphrase { salt, params }
+            }
+            SLOT_RECIPIENTS => {
+                let mut count = [0u8; 1];
+                if read_full(reader, &mut count)? < 1 {
+                    return Err(CryptoError::TruncatedCiphertext.into());
+                }
+                raw.push(count[0]);
+                let mut stanzas = Vec::with_capacity(count[0] as usize);
+                for _ in 0..count[0] {
+                    let mut data = [0u8; STANZA_LEN];
+                    if read_full(reader, &mut data)? < STANZA_LEN {
+                        return Err(CryptoError::TruncatedCiphertext.into());
+                    }
+                    raw.extend(data);
+                    let mut stanza = Stanza {
+                        ephemeral: [0; 32],
+                        wrapped: [0; STANZA_LEN - 32],
+                    };
+                    stanza.ephemeral.copy_from_slice(&data[..32]);
+                    stanza.wrapped.copy_from_slice(&data[32..]);
+                    stanzas.push(stanza);
+                }
+                KeySlot::Recipients(stanzas)
+            }
+            _ => return Err(CryptoError::InvalidCiphertext("unknown key slot").into()),
+        };
+        let header = Self {
+            algorithm,
+            chunk_size,
+            nonce,
+            slot,
+        };
+        Ok((header, raw))
+    }
+
+    /// The stream key for this header, if `key` is the right kind of key.
+    fn key(&self, key: &EncryptionKey) -> Result<Key> {
+        match (&self.slot, key) {
+            (KeySlot::KeyFile, EncryptionKey::KeyFile(path)) => load_key_file(path),
+            (KeySlot::Passphrase { salt, params }, EncryptionKey::Passphrase(passphrase)) => {
+                derive_key(passphrase, salt, params.clone())
+            }
+            (KeySlot::Recipients(stanzas), EncryptionKey::Identity(path)) => {
+                let identity = X25519Identity::load(path)?;
+                let file_key = stanzas
+                    .iter()
+                    .find_map(|stanza| identity.unwrap(stanza))
+                    .ok_or(CryptoError::AuthenticationFailed)?;
+                Ok(file_key)
+            }
+            (KeySlot::KeyFile, _) => anyhow::bail!("The data is encrypted with a key file"),
+            (KeySlot::Passphrase { .. }, _) => {
+                anyhow::bail!("The data is encrypted with a passphrase")
+            }
+            (KeySlot::Recipients(_), _) => {
+                anyhow::bail!("The data is encrypted to recipients, decrypt it with an identity")
+            }
+        }
+    }
+}
+
+fn encrypt_stream(
+    cipher: &StreamCipher,
+    header: &Header,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let header_bytes = header.encode();
+    writer.write_all(&header_bytes)?;
+    match cipher {
+        StreamCipher::ChaCha20Poly1305(aead) => {
+            seal_chunks(aead.clone(), header, &header_bytes, reader, writer)
+        }
+        StreamCipher::XChaCha20Poly1305(aead) => {
+            seal_chunks(aead.clone(), header, &header_bytes, reader, writer)
+        }
+        StreamCipher::Aes256Gcm(aead) => {
+            seal_chunks(*aead.clone(), header, &header_bytes, reader, writer)
+        }
+    }
+}
+
+fn seal_chunks<A>(
+    aead: A,
+    header: &Header,
+    header_bytes: &[u8],
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<()>
+where
+    A: AeadInPlace + KeyInit,
+    A::NonceSize: Sub<U5>,
+    <A::NonceSize as Sub<U5>>::Output: ArrayLength<u8>,
+{
+    let chunk_size = header.chunk_size;
+    let mut encryptor = EncryptorBE32::from_aead(aead, GenericArray::from_slice(&header.nonce));
+    let mut chunk = vec![0u8; chunk_size];
+    let mut next = vec![0u8; chunk_size];
+    let mut len = read_full(reader, &mut chunk)?;
+    loop {
+        // read one chunk ahead, the last chunk is sealed differently
+        let next_len = if len == chunk_size {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+        let payload = Payload {
+            msg: &chunk[..len],
+            aad: header_bytes,
+        };
+        if next_len == 0 {
+            let sealed = encryptor
+                .encrypt_last(payload)
+                .map_err(|e| anyhow::anyhow!("Error encrypting: {}", e))?;
+            writer.write_all(&sealed)?;
+            break;
+        }
+        let sealed = encryptor
+            .encrypt_next(payload)
+            .map_err(|e| anyhow::anyhow!("Error encrypting: {}", e))?;
+        writer.write_all(&sealed)?;
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn decrypt_stream(
+    key: &EncryptionKey,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<Vec<u8>> {
+    let (header, header_bytes) = Header::read(reader)?;
+    let cipher = StreamCipher::new(header.algorithm, &header.key(key)?);
+    decrypt_chunks(&cipher, &header, &header_bytes, reader, writer)?;
+    Ok(header_bytes)
+}
+
+fn decrypt_chunks(
+    cipher: &StreamCipher,
+    header: &Header,
+    header_bytes: &[u8],
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    match cipher {
+        StreamCipher::ChaCha20Poly1305(aead) => {
+            open_chunks(aead.clone(), header, header_bytes, reader, writer)
+        }
+        StreamCipher::XChaCha20Poly1305(aead) => {
+            open_chunks(aead.clone(), header, header_bytes, reader, writer)
+        }
+        StreamCipher::Aes256Gcm(aead) => {
+            open_chunks(*aead.clone(), header, header_bytes, reader, writer)
+        }
+    }
+}
+
+fn open_chunks<A>(
+    aead: A,
+    header: &Header,
+    header_bytes: &[u8],
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<()>
+where
+    A: AeadInPlace + KeyInit,
+    A::NonceSize: Sub<U5>,
+    <A::NonceSize as Sub<U5>>::Output: ArrayLength<u8>,
+{
+    let mut decryptor = DecryptorBE32::from_aead(aead, GenericArray::from_slice(&header.nonce));
+    let sealed_size = header.chunk_size + TAG_LEN;
+    let mut chunk = vec![0u8; sealed_size];
+    let mut next = vec![0u8; sealed_size];
+    let mut len = read_full(reader, &mut chunk)?;
+    loop {
+        let next_len = if len == sealed_size {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+        if len < TAG_LEN {
+            return Err(CryptoError::TruncatedCiphertext.into());
+        }
+        let payload = Payload {
+            msg: &chunk[..len],
+            aad: header_bytes,
+        };
+        if next_len == 0 {
+            let plaintext = decryptor
+                .decrypt_last(payload)
+                .map_err(|_| CryptoError::AuthenticationFailed)?;
+            writer.write_all(&plaintext)?;
+            break;
+        }
+        let plaintext = decryptor
+            .decrypt_next(payload)
+            .map_err(|_| CryptoError::AuthenticationFailed)?;
+        writer.write_all(&plaintext)?;
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Drops ASCII whitespace, so armored input may be wrapped or end in a newline.
+struct SkipWhitespace<R>(R);
+
+impl<R: Read> Read for SkipWhitespace<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.0.read(buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut kept = 0;
+            for i in 0..n {
+                if !buf[i].is_ascii_whitespace() {
+                    buf[kept] = buf[i];
+                    kept += 1;
+                }
+            }
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+/// Fill `buf` unless the reader hits EOF first, returning the bytes read.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::fuzz_rng;
+    use rand::Rng;
+    use std::fs;
+
+    const TEST_CHUNK: usize = 64;
+    const ALGORITHMS: [TextCipher; 3] = [
+        TextCipher::ChaCha20Poly1305,
+        TextCipher::XChaCha20Poly1305,
+        TextCipher::Aes256Gcm,
+    ];
+    /// Header length of a key file stream with a 96-bit nonce.
+    const FIXED_HEADER_LEN: usize = PREFIX_LEN + 12 - NONCE_OVERHEAD + 1;
+
+    fn new_key() -> Key {
+        ChaCha20Poly1305::generate_key(&mut OsRng)
+    }
+
+    fn encrypt(algorithm: TextCipher, key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let header = Header::new(algorithm, TEST_CHUNK, KeySlot::KeyFile);
+        let cipher = StreamCipher::new(algorithm, key);
+        encrypt_stream(&cipher, &header, &mut &data[..], &mut out)?;
+        Ok(out)
+    }
+
+    fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut reader = data;
+        let (header, header_bytes) = Header::read(&mut reader)?;
+        let cipher = StreamCipher::new(header.algorithm, key);
+        decrypt_chunks(&cipher, &header, &header_bytes, &mut reader, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_stream_roundtrip() -> Result<()> {
+        let key = new_key();
+        for algorithm in ALGORITHMS {
+            let header_len = PREFIX_LEN + nonce_prefix_len(algorithm) + 1;
+            for len in [
+                0,
+                1,
+                TEST_CHUNK - 1,
+                TEST_CHUNK,
+                TEST_CHUNK + 1,
+                3 * TEST_CHUNK,
+            ] {
+                let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                let encrypted = encrypt(algorithm, &key, &data)?;
+                let chunks = len.div_ceil(TEST_CHUNK).max(1);
+                assert_eq!(encrypted.len(), header_len + len + chunks * TAG_LEN);
+                assert_eq!(decrypt(&key, &encrypted)?, data, "{} {}", algorithm, len);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_algorithm_in_header() -> Result<()> {
+        let key = new_key();
+        let chacha = encrypt(TextCipher::ChaCha20Poly1305, &key, b"hello")?;
+        // claiming another algorithm in the header fails authentication
+        for id in [ALGORITHM_AES256GCM, ALGORITHM_XCHACHA20POLY1305] {
+            let mut tampered = chacha.clone();
+            tampered[5] = id;
+            assert!(decrypt(&key, &tampered).is_err());
+        }
+        let mut unknown = chacha.clone();
+        unknown[5] = 0xff;
+        let err = decrypt(&key, &unknown).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::InvalidCiphertext("unknown algorithm"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_text_encrypt_armor() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("chacha20poly1305.key");
+        fs::write(&key, new_key())?;
+        let key = EncryptionKey::KeyFile(key.to_str().unwrap());
+        let data: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+        for (armor, algorithm) in [false, true].into_iter().zip(ALGORITHMS) {
+            let mut encrypted = Vec::new();
+            process_text_encrypt(&mut &data[..], &mut encrypted, &key, algorithm, armor)?;
+            assert_eq!(encrypted.starts_with(MAGIC), !armor);
+            if armor {
+                assert!(encrypted.is_ascii());
+                // wrapped armor still decrypts
+                encrypted = encrypted
+                    .chunks(64)
+                    .flat_map(|line| line.iter().copied().chain(*b"\r\n"))
+                    .collect();
+            }
+            let mut decrypted = Vec::new();
+            process_text_decrypt(&mut &encrypted[..], &mut decrypted, &key)?;
+            assert_eq!(decrypted, data);
+        }
+
+        let err = process_text_decrypt(&mut &b"!!!!"[..], &mut Vec::new(), &key).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::InvalidCiphertext("invalid armor"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_passphrase() -> Result<()> {
+        // cheap parameters to keep the tests fast
+        let slot = KeySlot::Passphrase {
+            salt: [3; SALT_LEN],
+            params: argon2::Params::new(64, 1, 1, None).unwrap(),
+        };
+        let algorithm = TextCipher::ChaCha20Poly1305;
+        let header = Header::new(algorithm, TEST_CHUNK, slot);
+        let passphrase = EncryptionKey::Passphrase("correct horse");
+        let cipher = StreamCipher::new(algorithm, &header.key(&passphrase)?);
+        let mut encrypted = Vec::new();
+        encrypt_stream(&cipher, &header, &mut &b"hello"[..], &mut encrypted)?;
+
+        let mut decrypted = Vec::new();
+        decrypt_stream(&passphrase, &mut &encrypted[..], &mut decrypted)?;
+        assert_eq!(decrypted, b"hello");
+
+        let wrong = EncryptionKey::Passphrase("wrong horse");
+        let err = decrypt_stream(&wrong, &mut &encrypted[..], &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::AuthenticationFailed)
+        );
+        let key_file = EncryptionKey::KeyFile("fixtures/chacha20poly1305.key");
+        assert!(decrypt_stream(&key_file, &mut &encrypted[..], &mut Vec::new()).is_err());
+
+        // the KDF parameters are authenticated too
+        let mut tampered = encrypted.clone();
+        tampered[FIXED_HEADER_LEN + SALT_LEN + 3] ^= 1;
+        assert!(decrypt_stream(&passphrase, &mut &tampered[..], &mut Vec::new()).is_err());
+
+        // and bounded before they are used, so a crafted header cannot stall decryption
+        for param in 0..3 {
+            let mut crafted = encrypted.clone();
+            let start = FIXED_HEADER_LEN + SALT_LEN + param * 4;
+            crafted[start..start + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+            let err = decrypt_stream(&passphrase, &mut &crafted[..], &mut Vec::new()).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<CryptoError>(),
+                Some(&CryptoError::InvalidCiphertext("KDF parameters too high"))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_recipients() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut recipients = Vec::new();
+        let mut identities = Vec::new();
+        for name in ["alice", "bob"] {
+            let identity = X25519Identity::generate();
+            let sk = dir.path().join(format!("{}.sk", name));
+            let pk = dir.path().join(format!("{}.pk", name));
+            fs::write(&sk, identity.to_bytes())?;
+            fs::write(&pk, identity.public_key())?;
+            identities.push(sk.to_str().unwrap().to_string());
+            recipients.push(pk.to_str().unwrap().to_string());
+        }
+        let eve = dir.path().join("eve.sk");
+        fs::write(&eve, X25519Identity::generate().to_bytes())?;
+
+        let mut encrypted = Vec::new();
+        let key = EncryptionKey::Recipients(&recipients);
+        let algorithm = TextCipher::XChaCha20Poly1305;
+        process_text_encrypt(
+            &mut &b"shared secret"[..],
+            &mut encrypted,
+            &key,
+            algorithm,
+            false,
+        )?;
+        for identity in &identities {
+            let mut decrypted = Vec::new();
+            let key = EncryptionKey::Identity(identity);
+            process_text_decrypt(&mut &encrypted[..], &mut decrypted, &key)?;
+            assert_eq!(decrypted, b"shared secret");
+        }
+        let key = EncryptionKey::Identity(eve.to_str().unwrap());
+        let err = process_text_decrypt(&mut &encrypted[..], &mut Vec::new(), &key).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::AuthenticationFailed)
+        );
+        let key = EncryptionKey::KeyFile("fixtures/chacha20poly1305.key");
+        assert!(process_text_decrypt(&mut &encrypted[..], &mut Vec::new(), &key).is_err());
+
+        // swapping in a stanza for another recipient breaks the header authentication
+        let mut tampered = encrypted.clone();
+        let stanza = PREFIX_LEN + nonce_prefix_len(algorithm) + 2;
+        tampered.copy_within(stanza + STANZA_LEN..stanza + 2 * STANZA_LEN, stanza);
+        let key = EncryptionKey::Identity(&identities[1]);
+        assert!(process_text_decrypt(&mut &tampered[..], &mut Vec::new(), &key).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_fuzz() -> Result<()> {
+        let mut rng = fuzz_rng();
+        let key = new_key();
+        let algorithm = TextCipher::ChaCha20Poly1305;
+        let encrypted = encrypt(algorithm, &key, &[7u8; 2 * TEST_CHUNK + 3])?;
+        for _ in 0..500 {
+            // random garbage, random prefixes and random bit flips must never panic
+            let mut data = match rng.gen_range(0..3) {
+                0 => (0..rng.gen_range(0..300)).map(|_| rng.gen()).collect(),
+                1 => encrypted[..rng.gen_range(0..encrypted.len())].to_vec(),
+                _ => encrypted.clone(),
+            };
+            if !data.is_empty() && rng.gen_bool(0.5) {
+                let i = rng.gen_range(0..data.len());
+                data[i] ^= 1 << rng.gen_range(0..8);
+            }
+            if data == encrypted {
+                continue;
+            }
+            let err = decrypt(&key, &data).unwrap_err();
+            assert!(err.downcast_ref::<CryptoError>().is_some(), "{}", err);
+        }
+
+        let err = decrypt(&key, &encrypted[..FIXED_HEADER_LEN - 1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::TruncatedCiphertext)
+        );
+        let err = decrypt(&key, &encrypted[..FIXED_HEADER_LEN + TAG_LEN - 1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::TruncatedCiphertext)
+        );
+        let err = decrypt(&key, &encrypted[..encrypted.len() - 1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::AuthenticationFailed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_tampering() -> Result<()> {
+        let key = new_key();
+        let data = vec![42u8; 3 * TEST_CHUNK + 10];
+        let encrypted = encrypt(TextCipher::Aes256Gcm, &key, &data)?;
+        let sealed = TEST_CHUNK + TAG_LEN;
+        let chunk = |i: usize| FIXED_HEADER_LEN + i * sealed..FIXED_HEADER_LEN + (i + 1) * sealed;
+
+        // truncated at a chunk boundary
+        assert!(decrypt(&key, &encrypted[..chunk(2).end]).is_err());
+        // truncated mid-chunk
+        assert!(decrypt(&key, &encrypted[..encrypted.len() - 1]).is_err());
+        // swapped chunks
+        let mut swapped = encrypted.clone();
+        swapped[chunk(0)].copy_from_slice(&encrypted[chunk(1)]);
+        swapped[chunk(1)].copy_from_slice(&encrypted[chunk(0)]);
+        assert!(decrypt(&key, &swapped).is_err());
+        // header is authenticated
+        let mut header = encrypted.clone();
+        header[FIXED_HEADER_LEN - 2] ^= 1;
+        assert!(decrypt(&key, &header).is_err());
+        // wrong key
+        assert!(decrypt(&new_key(), &encrypted).is_err());
+        assert!(decrypt(&key, b"not encrypted").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+/// Failures of the key, signature and ciphertext parsers. They travel inside
+/// `anyhow::Error`, callers that need to tell them apart can `downcast_ref`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("Invalid {algorithm} key length: expected {expected} bytes, got {actual}")]
+    InvalidKeyLength {
+        algorithm: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid {algorithm} signature length: expected {expected} bytes, got {actual}")]
+    InvalidSignatureLength {
+        algorithm: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A signature that is not valid base64, or whose encoding is not valid, e.g. r = 0.
+    #[error("Malformed {0} signature")]
+    MalformedSignature(&'static str),
+    #[error("Ciphertext is truncated")]
+    TruncatedCiphertext,
+    #[error("Invalid ciphertext: {0}")]
+    InvalidCiphertext(&'static str),
+    #[error("Authentication failed: wrong key, or the data was truncated or tampered with")]
+    AuthenticationFailed,
+    /// A well-formed signature, token, code or password that does not match.
+    #[error("{0}")]
+    VerificationFailed(String),
+}
@@ -1,64 +1,403 @@
-use rand::seq::SliceRandom;
-
-const UPPER_CASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-const LOWER_CASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
-const NUMBERS: &[u8] = b"0123456789";
-const SYMBOLS: &[u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
-
-pub fn process_genpass(
-    length: u8,
-    upper: bool,
-    lower: bool,
-    number: bool,
-    symbol: bool,
-) -> anyhow::Result<String> {
-    let mut rng = rand::thread_rng();
-    let mut password = Vec::new();
-    let mut chars = Vec::new();
+use std::sync::OnceLock;
 
-    if upper {
-        chars.extend_from_slice(UPPER_CASE);
-        password.push(
-            *UPPER_CASE
-                .choose(&mut rng)
-                .expect("chars won't be empty in this context"),
-        );
+use crate::cli::GenPassFormat;
+use rand::{seq::SliceRandom, Rng};
+
+const UPPER_CASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER_CASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const NUMBERS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+const VOWELS: &str = "aeiouAEIOU";
+/// The EFF long wordlist for diceware: one `<dice rolls>\t<word>` entry per line.
+const EFF_LARGE_WORDLIST: &str = include_str!("../../assets/eff_large_wordlist.txt");
+
+fn wordlist() -> &'static [&'static str] {
+    static WORDLIST: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDLIST.get_or_init(|| {
+        EFF_LARGE_WORDLIST
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .collect()
+    })
+}
+
+/// The character classes a password is drawn from. Every generated password
+/// contains at least one character of each class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClasses {
+    classes: Vec<Vec<char>>,
+}
+
+impl CharClasses {
+    /// Select from the built-in uppercase, lowercase, number and symbol classes.
+    pub fn new(upper: bool, lower: bool, number: bool, symbol: bool) -> Self {
+        let classes = [
+            (upper, UPPER_CASE),
+            (lower, LOWER_CASE),
+            (number, NUMBERS),
+            (symbol, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, class)| class.chars().collect())
+        .collect();
+        Self { classes }
     }
 
-    if lower {
-        chars.extend_from_slice(LOWER_CASE);
-        password.push(
-            *LOWER_CASE
-                .choose(&mut rng)
-                .expect("chars won't be empty in this context"),
-        );
+    /// Use custom character sets, each one treated as its own class.
+    pub fn custom<S: AsRef<str>>(charsets: &[S]) -> Self {
+        let classes = charsets
+            .iter()
+            .map(|charset| {
+                let mut chars: Vec<char> = charset.as_ref().chars().collect();
+                // duplicates would make some characters more likely than others
+                chars.sort_unstable();
+                chars.dedup();
+                chars
+            })
+            .collect();
+        Self { classes }
     }
 
-    if number {
-        chars.extend_from_slice(NUMBERS);
-        password.push(
-            *NUMBERS
-                .choose(&mut rng)
-                .expect("chars won't be empty in this context"),
-        );
+    /// Remove the given characters (e.g. look-alikes such as "O0Il1") from every class.
+    pub fn exclude(mut self, excluded: &str) -> anyhow::Result<Self> {
+        for class in self.classes.iter_mut() {
+            let original: String = class.iter().collect();
+            class.retain(|c| !excluded.contains(*c));
+            if class.is_empty() {
+                anyhow::bail!("All characters of class {:?} are excluded", original);
+            }
+        }
+        Ok(self)
     }
 
-    if symbol {
-        chars.extend_from_slice(SYMBOLS);
-        password.push(
-            *SYMBOLS
-                .choose(&mut rng)
-                .expect("chars won't be empty in this context"),
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
+
+impl Default for CharClasses {
+    fn default() -> Self {
+        Self::new(true, true, true, true)
+    }
+}
+
+/// Generate a password of exactly `length` characters drawn from `classes`,
+/// containing at least one character from each of them.
+pub fn process_genpass(length: u8, classes: &CharClasses) -> anyhow::Result<String> {
+    if classes.is_empty() {
+        anyhow::bail!("At least one character class must be enabled");
+    }
+    if classes.classes.iter().any(|class| class.is_empty()) {
+        anyhow::bail!("Character classes must not be empty");
+    }
+    let length = length as usize;
+    if length < classes.len() {
+        anyhow::bail!(
+            "Password length {} is too short to include all {} enabled character classes",
+            length,
+            classes.len()
         );
     }
 
-    for _ in 0..length - 4 {
+    let mut rng = rand::thread_rng();
+    let mut password: Vec<char> = classes
+        .classes
+        .iter()
+        .map(|class| {
+            *class
+                .choose(&mut rng)
+                .expect("character classes are never empty")
+        })
+        .collect();
+
+    let chars = classes.classes.concat();
+    while password.len() < length {
         let c = chars
             .choose(&mut rng)
             .expect("chars won't be empty in this context");
         password.push(*c)
     }
     password.shuffle(&mut rng);
-    let password = String::from_utf8(password)?;
+    Ok(password.into_iter().collect())
+}
+
+/// Generate a diceware passphrase of `words` words from the EFF long wordlist.
+/// Returns the passphrase together with its entropy in bits.
+pub fn process_genphrase(
+    words: u8,
+    separator: &str,
+    capitalize: bool,
+    digit: bool,
+) -> anyhow::Result<(String, f64)> {
+    if words == 0 {
+        anyhow::bail!("Passphrase must have at least one word");
+    }
+    let wordlist = wordlist();
+
+    let mut rng = rand::thread_rng();
+    let mut phrase: Vec<String> = (0..words)
+        .map(|_| {
+            let word = *wordlist
+                .choose(&mut rng)
+                .expect("wordlist won't be empty in this context");
+            if capitalize {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    let mut entropy = words as f64 * (wordlist.len() as f64).log2();
+
+    if digit {
+        // a random digit appended to a random word
+        let index = rng.gen_range(0..phrase.len());
+        let d = *NUMBERS
+            .as_bytes()
+            .choose(&mut rng)
+            .expect("chars won't be empty in this context");
+        phrase[index].push(d as char);
+        entropy += (NUMBERS.len() as f64).log2() + (phrase.len() as f64).log2();
+    }
+    Ok((phrase.join(separator), entropy))
+}
+
+/// Generate a password following `pattern`, where each placeholder is replaced
+/// by a random character of its class and everything else is kept literally:
+///
+/// - `A` / `a`: uppercase / lowercase letter
+/// - `C` / `c`: uppercase / lowercase consonant
+/// - `V` / `v`: uppercase / lowercase vowel
+/// - `9`: number
+/// - `!`: symbol
+/// - `*`: any of the above
+/// - `\`: escapes the next character, e.g. `\9` for a literal `9`
+pub fn process_genpattern(pattern: &str, exclude: &str) -> anyhow::Result<String> {
+    let mut rng = rand::thread_rng();
+    let mut password = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let class: Vec<char> = match c {
+            'A' => UPPER_CASE.chars().collect(),
+            'a' => LOWER_CASE.chars().collect(),
+            'C' => UPPER_CASE
+                .chars()
+                .filter(|c| !VOWELS.contains(*c))
+                .collect(),
+            'c' => LOWER_CASE
+                .chars()
+                .filter(|c| !VOWELS.contains(*c))
+                .collect(),
+            'V' => UPPER_CASE.chars().filter(|c| VOWELS.contains(*c)).collect(),
+            'v' => LOWER_CASE.chars().filter(|c| VOWELS.contains(*c)).collect(),
+            '9' => NUMBERS.chars().collect(),
+            '!' => SYMBOLS.chars().collect(),
+            '*' => [UPPER_CASE, LOWER_CASE, NUMBERS, SYMBOLS]
+                .concat()
+                .chars()
+                .collect(),
+            '\\' => {
+                let literal = chars
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Pattern must not end with an escape"))?;
+                password.push(literal);
+                continue;
+            }
+            literal => {
+                password.push(literal);
+                continue;
+            }
+        };
+        let class: Vec<char> = class
+            .into_iter()
+            .filter(|c| !exclude.contains(*c))
+            .collect();
+        let c = class
+            .choose(&mut rng)
+            .ok_or_else(|| anyhow::anyhow!("All characters of placeholder {:?} are excluded", c))?;
+        password.push(*c);
+    }
     Ok(password)
 }
+
+/// Render generated passwords as plain lines, a JSON array or a single-column CSV.
+/// When `hashes` is not empty each password is paired with its hash: tab
+/// separated, as `{"password", "hash"}` objects or as a second CSV column.
+pub fn process_genpass_output(
+    passwords: &[String],
+    hashes: &[String],
+    format: GenPassFormat,
+) -> anyhow::Result<String> {
+    if hashes.is_empty() {
+        let output = match format {
+            GenPassFormat::Plain => passwords.join("\n"),
+            GenPassFormat::Json => serde_json::to_string_pretty(passwords)?,
+            GenPassFormat::Csv => to_csv(&["password"], passwords.iter().map(|p| vec![p]))?,
+        };
+        return Ok(output);
+    }
+
+    let pairs = passwords.iter().zip(hashes);
+    let output = match format {
+        GenPassFormat::Plain => pairs
+            .map(|(password, hash)| format!("{}\t{}", password, hash))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        GenPassFormat::Json => {
+            let entries: Vec<_> = pairs
+                .map(|(password, hash)| serde_json::json!({ "password": password, "hash": hash }))
+                .collect();
+            serde_json::to_string_pretty(&entries)?
+        }
+        GenPassFormat::Csv => to_csv(
+            &["password", "hash"],
+            pairs.map(|(password, hash)| vec![password, hash]),
+        )?,
+    };
+    Ok(output)
+}
+
+fn to_csv<'a>(
+    headers: &[&str],
+    rows: impl Iterator<Item = Vec<&'a String>>,
+) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(headers)?;
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    let output = String::from_utf8(writer.into_inner()?)?;
+    Ok(output.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genpass_exact_length() -> anyhow::Result<()> {
+        let lower = CharClasses::new(false, true, false, false);
+        for length in [1, 3, 4, 16, 255] {
+            let password = process_genpass(length, &lower)?;
+            assert_eq!(password.len(), length as usize);
+        }
+        let password = process_genpass(4, &CharClasses::default())?;
+        assert_eq!(password.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_respects_classes() -> anyhow::Result<()> {
+        let password = process_genpass(32, &CharClasses::new(false, false, true, false))?;
+        assert!(password.chars().all(|c| c.is_ascii_digit()));
+
+        let password = process_genpass(2, &CharClasses::new(true, false, false, true))?;
+        assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+        assert!(password.chars().any(|c| SYMBOLS.contains(c)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_invalid_options() {
+        let none = CharClasses::new(false, false, false, false);
+        assert!(process_genpass(16, &none).is_err());
+        assert!(process_genpass(3, &CharClasses::default()).is_err());
+        let upper = CharClasses::new(true, false, false, false);
+        assert!(process_genpass(0, &upper).is_err());
+    }
+
+    #[test]
+    fn test_genpass_custom_charset_and_exclude() -> anyhow::Result<()> {
+        let classes = CharClasses::custom(&["abcabc", "xyz"]).exclude("cz")?;
+        let password = process_genpass(64, &classes)?;
+        assert_eq!(password.chars().count(), 64);
+        assert!(password.chars().all(|c| "abxy".contains(c)));
+
+        let classes = CharClasses::default().exclude("O0Il1")?;
+        let password = process_genpass(255, &classes)?;
+        assert!(!password.chars().any(|c| "O0Il1".contains(c)));
+
+        assert!(CharClasses::default().exclude(NUMBERS).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genphrase() -> anyhow::Result<()> {
+        let (phrase, entropy) = process_genphrase(6, " ", false, false)?;
+        assert_eq!(phrase.split(' ').count(), 6);
+        assert!(phrase
+            .chars()
+            .all(|c| c == ' ' || c == '-' || c.is_ascii_lowercase()));
+        // log2(7776) is about 12.925 bits per word
+        assert!((entropy - 77.55).abs() < 0.01);
+
+        let (phrase, entropy) = process_genphrase(4, ".", true, true)?;
+        let words: Vec<&str> = phrase.split('.').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words
+            .iter()
+            .all(|w| w.starts_with(|c: char| c.is_ascii_uppercase())));
+        assert_eq!(phrase.chars().filter(|c| c.is_ascii_digit()).count(), 1);
+        assert!(entropy > 4.0 * 12.9 + 3.3);
+
+        assert!(process_genphrase(0, "-", false, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpattern() -> anyhow::Result<()> {
+        let password = process_genpattern("Cvccvc-99-!", "")?;
+        let chars: Vec<char> = password.chars().collect();
+        assert_eq!(chars.len(), 11);
+        assert!(chars[0].is_ascii_uppercase() && !VOWELS.contains(chars[0]));
+        assert!(VOWELS.contains(chars[1]) && chars[1].is_ascii_lowercase());
+        assert_eq!(chars[6], '-');
+        assert!(chars[7].is_ascii_digit() && chars[8].is_ascii_digit());
+        assert!(SYMBOLS.contains(chars[10]));
+
+        assert_eq!(process_genpattern("\\9\\!x", "")?, "9!x");
+        let password = process_genpattern("99999999", "012345678")?;
+        assert_eq!(password, "99999999");
+        assert!(process_genpattern("9", NUMBERS).is_err());
+        assert!(process_genpattern("a\\", "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_output() -> anyhow::Result<()> {
+        let passwords = vec!["abc".to_string(), "a,\"b".to_string()];
+        assert_eq!(
+            process_genpass_output(&passwords, &[], GenPassFormat::Plain)?,
+            "abc\na,\"b"
+        );
+        let json = process_genpass_output(&passwords, &[], GenPassFormat::Json)?;
+        assert_eq!(serde_json::from_str::<Vec<String>>(&json)?, passwords);
+        assert_eq!(
+            process_genpass_output(&passwords, &[], GenPassFormat::Csv)?,
+            "password\nabc\n\"a,\"\"b\""
+        );
+
+        let hashes = vec!["$h1".to_string(), "$h2".to_string()];
+        assert_eq!(
+            process_genpass_output(&passwords, &hashes, GenPassFormat::Plain)?,
+            "abc\t$h1\na,\"b\t$h2"
+        );
+        let json = process_genpass_output(&passwords, &hashes, GenPassFormat::Json)?;
+        let json: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(json[1]["hash"], "$h2");
+        assert_eq!(
+            process_genpass_output(&passwords, &hashes, GenPassFormat::Csv)?,
+            "password,hash\nabc,$h1\n\"a,\"\"b\",$h2"
+        );
+        Ok(())
+    }
+}
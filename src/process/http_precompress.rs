@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{cli::CompressFormat, collect_files, process_compress};
+use anyhow::Result;
+use tracing::{info, warn};
+
+/// Extensions of files that are already compressed; compressing them again only costs CPU.
+/// `tmp` covers temp files left behind by an interrupted run.
+const SKIP_EXTENSIONS: &[&str] = &[
+    "gz", "br", "zst", "zz", "zip", "7z", "xz", "bz2", "rar", "tgz", "png", "jpg", "jpeg", "gif",
+    "webp", "avif", "ico", "mp3", "mp4", "m4a", "ogg", "webm", "mov", "woff", "woff2", "pdf",
+    "tmp",
+];
+
+/// Lists the `<extension> <path>` pairs that compression did not shrink, so
+/// the next run can treat them as unchanged. Like a sibling, an entry is only
+/// trusted while this file is newer than the source.
+const INCOMPRESSIBLE_FILE: &str = ".rcli-precompress";
+
+/// Counts files, not siblings: a file is compressed when at least one sibling
+/// was written, unchanged when every format was already up to date, and
+/// skipped otherwise.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PrecompressStats {
+    pub compressed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+}
+
+pub fn process_http_precompress(
+    dir: &Path,
+    formats: &[CompressFormat],
+    min_size: u64,
+    force: bool,
+) -> Result<PrecompressStats> {
+    let mut stats = PrecompressStats::default();
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+
+    let state_path = dir.join(INCOMPRESSIBLE_FILE);
+    let (known, known_since) = if force {
+        (BTreeSet::new(), None)
+    } else {
+        read_incompressible(&state_path)?
+    };
+    let mut incompressible = BTreeSet::new();
+
+    for path in files {
+        if path == state_path {
+            continue;
+        }
+        let metadata = fs::metadata(&path)?;
+        if metadata.len() < min_size || !is_compressible(&path) {
+            stats.skipped += 1;
+            continue;
+        }
+
+        let relative = path.strip_prefix(dir)?.to_string_lossy().into_owned();
+        let mut compressed = false;
+        let mut unchanged = true;
+        for format in formats {
+            let target = sibling_path(&path, *format);
+            let entry = format!("{} {}", format.extension(), relative);
+            if !force && is_up_to_date(&target, &metadata)? {
+                continue;
+            }
+            if known.contains(&entry) && known_since >= Some(metadata.modified()?) {
+                incompressible.insert(entry);
+                continue;
+            }
+            unchanged = false;
+            if compress_file(&path, &target, *format, metadata.len())? {
+                info!("Compressed {:?} to {:?}", path, target);
+                compressed = true;
+            } else {
+                incompressible.insert(entry);
+            }
+        }
+
+        if compressed {
+            stats.compressed += 1;
+        } else if unchanged {
+            stats.unchanged += 1;
+        } else {
+            stats.skipped += 1;
+        }
+    }
+
+    write_incompressible(&state_path, &incompressible)?;
+    Ok(stats)
+}
+
+fn read_incompressible(path: &Path) -> Result<(BTreeSet<String>, Option<SystemTime>)> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let entries = content.lines().map(str::to_owned).collect();
+            Ok((entries, Some(fs::metadata(path)?.modified()?)))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((BTreeSet::new(), None)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_incompressible(path: &Path, entries: &BTreeSet<String>) -> Result<()> {
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(entry);
+        content.push('\n');
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn is_compressible(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => !SKIP_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+        None => true,
+    }
+}
+
+fn sibling_path(path: &Path, format: CompressFormat) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(format.extension());
+    PathBuf::from(name)
+}
+
+fn is_up_to_date(target: &Path, source: &fs::Metadata) -> Result<bool> {
+    match fs::metadata(target) {
+        Ok(metadata) => Ok(metadata.modified()? >= source.modified()?),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Compress `path` into `target`, returning false (and removing any stale
+/// sibling) when the result would not be smaller than the original.
+fn compress_file(path: &Path, target: &Path, format: CompressFormat, size: u64) -> Result<bool> {
+    let parent = target.parent().unwrap_or(Path::new("."));
+    let tmp = tempfile::Builder::new()
+        .prefix(".rcli-")
+        .suffix(".tmp")
+        .tempfile_in(parent)?;
+
+    let source = File::open(path)?;
+    // temp files are created 0600, the sibling must stay as readable as the source
+    tmp.as_file()
+        .set_permissions(source.metadata()?.permissions())?;
+
+    let mut reader = BufReader::new(source);
+    let mut writer = BufWriter::new(tmp.as_file());
+    process_compress(
+        &mut reader,
+        &mut writer,
+        format,
+        Some(format.http_max_level()),
+    )?;
+    writer.flush()?;
+    drop(writer);
+
+    if tmp.as_file().metadata()?.len() >= size {
+        if target.exists() {
+            warn!("Removing stale {:?}, compression does not help", target);
+            fs::remove_file(target)?;
+        }
+        return Ok(false);
+    }
+    // rename so a running server never sees a partially written file
+    tmp.persist(target)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_decompress;
+
+    #[test]
+    fn test_process_http_precompress() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let content = fs::read("Cargo.toml")?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/Cargo.toml"), &content)?;
+        fs::write(dir.path().join("logo.png"), &content)?;
+
+        let formats = [CompressFormat::Gzip, CompressFormat::Brotli];
+        let stats = process_http_precompress(dir.path(), &formats, 0, false)?;
+        assert_eq!(stats.compressed, 1);
+        assert_eq!(stats.skipped, 1);
+        assert!(!dir.path().join("logo.png.gz").exists());
+
+        let compressed = fs::read(dir.path().join("sub/Cargo.toml.br"))?;
+        let mut decompressed = Vec::new();
+        process_decompress(
+            &mut &compressed[..],
+            &mut decompressed,
+            CompressFormat::Brotli,
+        )?;
+        assert_eq!(decompressed, content);
+
+        // sidecars are skipped and up-to-date files are left alone
+        let stats = process_http_precompress(dir.path(), &formats, 0, false)?;
+        assert_eq!(stats.compressed, 0);
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(stats.skipped, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_precompress_remembers_incompressible_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut noise = vec![0u8; 4096];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut noise[..]);
+        fs::write(dir.path().join("noise.bin"), &noise)?;
+        // left behind by an interrupted run
+        fs::write(
+            dir.path().join("index.html.gz.tmp"),
+            fs::read("Cargo.toml")?,
+        )?;
+
+        let formats = [CompressFormat::Gzip, CompressFormat::Zstd];
+        let stats = process_http_precompress(dir.path(), &formats, 0, false)?;
+        assert_eq!(stats.compressed, 0);
+        assert_eq!(stats.skipped, 2);
+        assert!(!dir.path().join("noise.bin.gz").exists());
+        assert!(!dir.path().join("index.html.gz.tmp.gz").exists());
+
+        let stats = process_http_precompress(dir.path(), &formats, 0, false)?;
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(stats.skipped, 1);
+
+        let stats = process_http_precompress(dir.path(), &formats, 0, true)?;
+        assert_eq!(stats.unchanged, 0);
+        assert_eq!(stats.skipped, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_precompress_zstd_window_fits_http() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("Cargo.toml"), fs::read("Cargo.toml")?)?;
+        process_http_precompress(dir.path(), &[CompressFormat::Zstd], 0, false)?;
+
+        let frame = fs::read(dir.path().join("Cargo.toml.zst"))?;
+        assert_eq!(frame[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+        // without the single segment flag the window descriptor follows the
+        // frame header descriptor, its top five bits are windowLog - 10
+        assert_eq!(frame[4] & 0x20, 0);
+        let window_log = 10 + (frame[5] >> 3);
+        assert!(window_log <= 23, "windowLog {}", window_log);
+        Ok(())
+    }
+}
@@ -59,6 +59,5 @@ pub fn process_jwt_verify(token: &str) -> Result<Claims> {
         &DecodingKey::from_secret(SECRET.as_ref()),
         &validation,
     )?;
-    println!("{:?}", token_data);
     Ok(token_data.claims)
 }
@@ -0,0 +1,215 @@
+use std::{env, fs, path::Path};
+
+use super::error::CryptoError;
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, OsRng, Payload},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+
+/// Prefix of passphrase encrypted key files, followed by the base64url encoded
+/// `salt || m_cost || t_cost || p_cost || nonce || ciphertext`.
+const ENCRYPTED_KEY_PREFIX: &str = "rcli:encrypted-key:v1:";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Salt plus the three big-endian u32 argon2id parameters.
+const HEADER_LEN: usize = SALT_LEN + KDF_PARAMS_LEN;
+/// `m_cost || t_cost || p_cost` as big-endian u32s.
+pub(super) const KDF_PARAMS_LEN: usize = 3 * 4;
+/// Upper bounds for argon2id parameters read from a header. The header is only
+/// authenticated after the key is derived, so a crafted file could otherwise
+/// make decryption allocate or spin without end.
+const MAX_KDF_MEMORY: u32 = 4 * 1024 * 1024;
+const MAX_KDF_ITERATIONS: u32 = 64;
+const MAX_KDF_PARALLELISM: u32 = 16;
+
+/// Environment variable holding the key passphrase.
+pub const PASSPHRASE_ENV: &str = "RCLI_KEY_PASSPHRASE";
+/// Environment variable naming a file descriptor to read the key passphrase from.
+pub const PASSPHRASE_FD_ENV: &str = "RCLI_KEY_PASSPHRASE_FD";
+
+pub fn is_encrypted_key(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_KEY_PREFIX.as_bytes())
+}
+
+/// Encrypt a key file with ChaCha20Poly1305 under an argon2id key derived
+/// from `passphrase`. The KDF parameters travel in the header, which is also
+/// authenticated, so they can be raised later without breaking old files.
+pub fn encrypt_key(key: &[u8], passphrase: &str, params: Params) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut header = salt.to_vec();
+    header.extend(params.m_cost().to_be_bytes());
+    header.extend(params.t_cost().to_be_bytes());
+    header.extend(params.p_cost().to_be_bytes());
+
+    let cipher = derive_cipher(passphrase, &salt, params)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: key,
+        aad: &header,
+    };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|e| anyhow::anyhow!("Error encrypting key: {}", e))?;
+
+    let mut data = header;
+    data.extend(nonce);
+    data.extend(ciphertext);
+    Ok(format!("{}{}\n", ENCRYPTED_KEY_PREFIX, URL_SAFE_NO_PAD.encode(data)).into_bytes())
+}
+
+pub fn decrypt_key(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let encoded = data
+        .strip_prefix(ENCRYPTED_KEY_PREFIX.as_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Not an encrypted key file"))?;
+    let data = URL_SAFE_NO_PAD.decode(encoded.trim_ascii())?;
+    if data.len() < HEADER_LEN + NONCE_LEN + 16 {
+        return Err(CryptoError::TruncatedCiphertext.into());
+    }
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let params = decode_kdf_params(&header[SALT_LEN..])?;
+
+    let cipher = derive_cipher(passphrase, &header[..SALT_LEN], params)?;
+    let payload = Payload {
+        msg: ciphertext,
+        aad: header,
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+/// Decode the argon2id parameters of a header, rejecting any beyond the bounds
+/// a legitimate file would use.
+pub(super) fn decode_kdf_params(data: &[u8]) -> Result<Params, CryptoError> {
+    let param = |i: usize| {
+        data.get(i * 4..i * 4 + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or(CryptoError::TruncatedCiphertext)
+    };
+    let (m_cost, t_cost, p_cost) = (param(0)?, param(1)?, param(2)?);
+    if m_cost > MAX_KDF_MEMORY || t_cost > MAX_KDF_ITERATIONS || p_cost > MAX_KDF_PARALLELISM {
+        return Err(CryptoError::InvalidCiphertext("KDF parameters too high"));
+    }
+    Params::new(m_cost, t_cost, p_cost, None)
+        .map_err(|_| CryptoError::InvalidCiphertext("invalid KDF parameters"))
+}
+
+/// Read a key file, decrypting it first if it is passphrase protected.
+pub fn read_key_file(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    if !is_encrypted_key(&data) {
+        return Ok(data);
+    }
+    let passphrase = read_passphrase(&format!("Enter passphrase for {}: ", path.display()), false)?;
+    decrypt_key(&data, &passphrase)
+}
+
+/// Get the key passphrase from `RCLI_KEY_PASSPHRASE`, the file descriptor in
+/// `RCLI_KEY_PASSPHRASE_FD`, or else by prompting on the terminal. With
+/// `confirm` the prompt asks twice, for passphrases about to be set.
+pub fn read_passphrase(prompt: &str, confirm: bool) -> Result<String> {
+    let passphrase = if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        passphrase
+    } else if let Ok(fd) = env::var(PASSPHRASE_FD_ENV) {
+        let fd: u32 = fd
+            .parse()
+            .map_err(|_| anyhow::anyhow!("{} must be a file descriptor", PASSPHRASE_FD_ENV))?;
+        let content = fs::read_to_string(format!("/dev/fd/{}", fd))?;
+        content.lines().next().unwrap_or_default().to_string()
+    } else {
+        let passphrase = rpassword::prompt_password(prompt).map_err(|e| {
+            anyhow::anyhow!(
+                "Cannot prompt for a passphrase ({}), set {} or {}",
+                e,
+                PASSPHRASE_ENV,
+                PASSPHRASE_FD_ENV
+            )
+        })?;
+        if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+            anyhow::bail!("Passphrases do not match");
+        }
+        passphrase
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("The passphrase must not be empty");
+    }
+    Ok(passphrase)
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<ChaCha20Poly1305> {
+    Ok(ChaCha20Poly1305::new(&derive_key(
+        passphrase, salt, params,
+    )?))
+}
+
+/// Stretch `passphrase` into a 256-bit key with argon2id.
+pub(super) fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Error deriving key: {}", e))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_params() -> Params {
+        // cheap parameters to keep the tests fast
+        Params::new(64, 1, 1, None).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_key() -> Result<()> {
+        let key = fs::read("fixtures/ed25519.sk")?;
+        let encrypted = encrypt_key(&key, "correct horse", test_params())?;
+        assert!(is_encrypted_key(&encrypted));
+        assert_eq!(decrypt_key(&encrypted, "correct horse")?, key);
+        assert!(decrypt_key(&encrypted, "wrong horse").is_err());
+
+        // the KDF parameters are authenticated
+        let mut data =
+            URL_SAFE_NO_PAD.decode(encrypted[ENCRYPTED_KEY_PREFIX.len()..].trim_ascii())?;
+        data[SALT_LEN + 3] ^= 1;
+        let tampered = format!("{}{}", ENCRYPTED_KEY_PREFIX, URL_SAFE_NO_PAD.encode(&data));
+        assert!(decrypt_key(tampered.as_bytes(), "correct horse").is_err());
+
+        let truncated = format!(
+            "{}{}",
+            ENCRYPTED_KEY_PREFIX,
+            URL_SAFE_NO_PAD.encode([0u8; 8])
+        );
+        assert!(decrypt_key(truncated.as_bytes(), "correct horse").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_kdf_params_bounds() {
+        let encode = |m: u32, t: u32, p: u32| [m, t, p].map(u32::to_be_bytes).concat();
+        let params = decode_kdf_params(&encode(19 * 1024, 2, 1)).unwrap();
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (19 * 1024, 2, 1)
+        );
+
+        let too_high = Err(CryptoError::InvalidCiphertext("KDF parameters too high"));
+        assert_eq!(decode_kdf_params(&encode(u32::MAX, 2, 1)), too_high);
+        assert_eq!(decode_kdf_params(&encode(64, u32::MAX, 1)), too_high);
+        assert_eq!(decode_kdf_params(&encode(64, 2, u32::MAX)), too_high);
+        assert_eq!(
+            decode_kdf_params(&encode(64, 0, 1)),
+            Err(CryptoError::InvalidCiphertext("invalid KDF parameters"))
+        );
+        assert_eq!(
+            decode_kdf_params(&[0; 8]),
+            Err(CryptoError::TruncatedCiphertext)
+        );
+    }
+}
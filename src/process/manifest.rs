@@ -0,0 +1,228 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::TextSignFormat, collect_files, process_text_sign, process_text_verify};
+use anyhow::Result;
+use serde::Serialize;
+
+/// One file of a manifest: `<blake3 hex> <size> <path>` per line, with `/`
+/// separated paths relative to the signed directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// Differences between a manifest and the directory it describes.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ManifestDiff {
+    pub changed: Vec<String>,
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Hash every file below `dir` into a manifest written to `output`, then sign
+/// the manifest and return the signature. `output` is skipped if it lives in `dir`.
+pub fn process_text_sign_manifest(
+    dir: &Path,
+    key: &str,
+    format: TextSignFormat,
+    output: &Path,
+) -> Result<String> {
+    let entries = build_manifest(dir, &[output])?;
+    fs::write(output, format_manifest(&entries))?;
+    process_text_sign(&path_str(output)?, key, format)
+}
+
+/// Check the manifest signature, then compare the manifest against `dir`.
+/// Returns whether the signature is valid together with the differences found.
+pub fn process_text_verify_manifest(
+    dir: &Path,
+    manifest: &Path,
+    key: &str,
+    format: TextSignFormat,
+    sig: &str,
+) -> Result<(bool, ManifestDiff)> {
+    let verified = process_text_verify(&path_str(manifest)?, key, format, sig)?;
+    let expected = parse_manifest(&fs::read_to_string(manifest)?)?;
+    let actual = build_manifest(dir, &[manifest])?;
+    Ok((verified, diff_manifest(&expected, &actual)))
+}
+
+fn build_manifest(dir: &Path, skip: &[&Path]) -> Result<Vec<ManifestEntry>> {
+    let skip = skip
+        .iter()
+        .filter_map(|p| p.canonicalize().ok())
+        .collect::<Vec<_>>();
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+
+    let mut entries = Vec::with_capacity(files.len());
+    for file in files {
+        if skip.contains(&file.canonicalize()?) || is_sig_of(&file, &skip) {
+            continue;
+        }
+        let mut hasher = blake3::Hasher::new();
+        let size = io::copy(&mut File::open(&file)?, &mut hasher)?;
+        entries.push(ManifestEntry {
+            path: relative_path(dir, &file)?,
+            size,
+            hash: hasher.finalize().to_hex().to_string(),
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn format_manifest(entries: &[ManifestEntry]) -> String {
+    entries
+        .iter()
+        .map(|e| format!("{} {} {}\n", e.hash, e.size, e.path))
+        .collect()
+}
+
+fn parse_manifest(content: &str) -> Result<Vec<ManifestEntry>> {
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(hash), Some(size), Some(path)) => Ok(ManifestEntry {
+                    path: path.to_string(),
+                    size: size.parse()?,
+                    hash: hash.to_string(),
+                }),
+                _ => anyhow::bail!("Invalid manifest line: {:?}", line),
+            }
+        })
+        .collect()
+}
+
+fn diff_manifest(expected: &[ManifestEntry], actual: &[ManifestEntry]) -> ManifestDiff {
+    let actual: BTreeMap<&str, &ManifestEntry> =
+        actual.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut diff = ManifestDiff::default();
+    for entry in expected {
+        match actual.get(entry.path.as_str()) {
+            Some(found) if *found != entry => diff.changed.push(entry.path.clone()),
+            Some(_) => {}
+            None => diff.missing.push(entry.path.clone()),
+        }
+    }
+    let expected: BTreeSet<&str> = expected.iter().map(|e| e.path.as_str()).collect();
+    diff.extra = actual
+        .keys()
+        .filter(|path| !expected.contains(*path))
+        .map(|path| path.to_string())
+        .collect();
+    diff
+}
+
+/// Whether `file` is the detached signature (`<manifest>.sig`) of a skipped manifest.
+fn is_sig_of(file: &Path, skip: &[PathBuf]) -> bool {
+    let Some(name) = file.to_str().and_then(|f| f.strip_suffix(".sig")) else {
+        return false;
+    };
+    Path::new(name)
+        .canonicalize()
+        .map(|p| skip.contains(&p))
+        .unwrap_or(false)
+}
+
+fn relative_path(dir: &Path, file: &Path) -> Result<String> {
+    let relative = file.strip_prefix(dir)?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+fn path_str(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(|p| p.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Path is not valid UTF-8: {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("bin"))?;
+        fs::write(dir.path().join("bin/rcli"), b"binary")?;
+        fs::write(dir.path().join("README.md"), b"readme")?;
+        fs::write(dir.path().join("LICENSE"), b"license")?;
+        let manifest = dir.path().join("MANIFEST");
+        let key = "fixtures/blake3.txt";
+
+        let sig = process_text_sign_manifest(dir.path(), key, TextSignFormat::Blake3, &manifest)?;
+        let entries = parse_manifest(&fs::read_to_string(&manifest)?)?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["LICENSE", "README.md", "bin/rcli"]);
+        assert_eq!(entries[2].size, 6);
+
+        // the detached signature next to the manifest is not an extra file
+        fs::write(dir.path().join("MANIFEST.sig"), &sig)?;
+        let (verified, diff) =
+            process_text_verify_manifest(dir.path(), &manifest, key, TextSignFormat::Blake3, &sig)?;
+        assert!(verified);
+        assert!(diff.is_empty());
+
+        fs::write(dir.path().join("bin/rcli"), b"tampered")?;
+        fs::remove_file(dir.path().join("LICENSE"))?;
+        fs::write(dir.path().join("extra.txt"), b"extra")?;
+        let (verified, diff) =
+            process_text_verify_manifest(dir.path(), &manifest, key, TextSignFormat::Blake3, &sig)?;
+        assert!(verified);
+        assert_eq!(
+            diff,
+            ManifestDiff {
+                changed: vec!["bin/rcli".to_string()],
+                missing: vec!["LICENSE".to_string()],
+                extra: vec!["extra.txt".to_string()],
+            }
+        );
+
+        fs::write(&manifest, b"0 0 forged\n")?;
+        let (verified, _) =
+            process_text_verify_manifest(dir.path(), &manifest, key, TextSignFormat::Blake3, &sig)?;
+        assert!(!verified);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_manifest_follows_symlinks() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("lib"))?;
+        fs::write(dir.path().join("lib/core.so"), b"core")?;
+        symlink("core.so", dir.path().join("lib/current.so"))?;
+        // a loop back to the root is not walked twice
+        symlink("..", dir.path().join("lib/root"))?;
+
+        let entries = build_manifest(dir.path(), &[])?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["lib/core.so", "lib/current.so"]);
+
+        symlink("missing", dir.path().join("dangling"))?;
+        assert!(build_manifest(dir.path(), &[]).is_err());
+        Ok(())
+    }
+}
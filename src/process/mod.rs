@@ -1,16 +1,61 @@
 mod b64;
+mod compress;
 mod csv_convert;
+mod ecdsa_sign;
+mod encrypt;
+mod error;
 mod gen_pass;
+mod http_precompress;
 mod http_serve;
 mod jwt;
+mod key_encryption;
+mod key_format;
+mod manifest;
+mod otp;
+mod passwd;
+mod recipient;
+mod seal;
 mod text;
 
 pub use b64::{process_decode, process_encode};
+pub use compress::{process_compress, process_decompress};
 pub use csv_convert::process_csv;
-pub use gen_pass::process_genpass;
+pub use encrypt::{process_text_decrypt, process_text_encrypt, EncryptionKey};
+pub use error::CryptoError;
+pub use gen_pass::{
+    process_genpass, process_genpass_output, process_genpattern, process_genphrase, CharClasses,
+};
+pub use http_precompress::{process_http_precompress, PrecompressStats};
 pub use http_serve::process_http_serve;
 pub use jwt::{process_jwt_sign, process_jwt_verify};
+pub use key_encryption::read_passphrase;
+pub use key_format::{process_text_key_convert, ConvertedKey};
+pub use manifest::{
+    process_text_sign_manifest, process_text_verify_manifest, ManifestDiff, ManifestEntry,
+};
+pub use otp::{
+    decode_otp_secret, process_hotp, process_hotp_verify, process_otp_secret, process_totp,
+    process_totp_verify, unix_time, TotpParams,
+};
+pub use passwd::{
+    process_passwd_check, process_passwd_hash, process_passwd_verify, CrackTimeReport,
+    PasswordHashParams, PasswordReport,
+};
+pub use seal::{process_text_open, process_text_seal};
 pub use text::{
-    process_text_decrypt, process_text_encrypt, process_text_generate, process_text_sign,
-    process_text_verify,
+    process_text_generate, process_text_key_generate, process_text_sign, process_text_verify,
+    write_key_file, GeneratedKey,
 };
+
+/// A seeded rng for the fuzz tests. The seed is printed with the test output,
+/// so a failure can be replayed with `RCLI_FUZZ_SEED=<seed> cargo test`.
+#[cfg(test)]
+fn fuzz_rng() -> rand::rngs::StdRng {
+    use rand::SeedableRng;
+    let seed = std::env::var("RCLI_FUZZ_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(rand::random);
+    eprintln!("fuzz seed: {}", seed);
+    rand::rngs::StdRng::seed_from_u64(seed)
+}
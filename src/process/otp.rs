@@ -0,0 +1,286 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::OtpAlgorithm;
+use anyhow::Result;
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use subtle::ConstantTimeEq;
+
+/// Parameters shared by every TOTP operation (RFC 6238).
+#[derive(Debug, Clone, Copy)]
+pub struct TotpParams {
+    pub digits: u32,
+    pub period: u64,
+    pub algorithm: OtpAlgorithm,
+}
+
+impl Default for TotpParams {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            period: 30,
+            algorithm: OtpAlgorithm::Sha1,
+        }
+    }
+}
+
+/// Decode a base32 secret as shown by authenticator apps: case-insensitive,
+/// with optional spaces and `=` padding.
+pub fn decode_otp_secret(secret: &str) -> Result<Vec<u8>> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let secret = BASE32_NOPAD.decode(normalized.as_bytes())?;
+    if secret.is_empty() {
+        anyhow::bail!("OTP secret must not be empty");
+    }
+    Ok(secret)
+}
+
+/// Compute an RFC 4226 HOTP code for `counter`.
+pub fn process_hotp(
+    secret: &[u8],
+    counter: u64,
+    digits: u32,
+    algorithm: OtpAlgorithm,
+) -> Result<String> {
+    if !(6..=10).contains(&digits) {
+        anyhow::bail!("OTP codes must have 6 to 10 digits");
+    }
+    let hash = match algorithm {
+        OtpAlgorithm::Sha1 => hmac_digest::<Hmac<sha1::Sha1>>(secret, counter)?,
+        OtpAlgorithm::Sha256 => hmac_digest::<Hmac<sha2::Sha256>>(secret, counter)?,
+        OtpAlgorithm::Sha512 => hmac_digest::<Hmac<sha2::Sha512>>(secret, counter)?,
+    };
+    // dynamic truncation, RFC 4226 section 5.3
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into()?) & 0x7fff_ffff;
+    let code = binary as u64 % 10u64.pow(digits);
+    Ok(format!("{:0width$}", code, width = digits as usize))
+}
+
+/// Compute an RFC 6238 TOTP code for the unix `time` in seconds.
+pub fn process_totp(secret: &[u8], time: u64, params: &TotpParams) -> Result<String> {
+    if params.period == 0 {
+        anyhow::bail!("TOTP period must be greater than zero");
+    }
+    process_hotp(
+        secret,
+        time / params.period,
+        params.digits,
+        params.algorithm,
+    )
+}
+
+/// Check a HOTP code against `counter..=counter + window`, returning the
+/// matching counter so the caller can resynchronize.
+pub fn process_hotp_verify(
+    secret: &[u8],
+    code: &str,
+    counter: u64,
+    window: u64,
+    digits: u32,
+    algorithm: OtpAlgorithm,
+) -> Result<Option<u64>> {
+    for c in counter..=counter.saturating_add(window) {
+        if codes_eq(&process_hotp(secret, c, digits, algorithm)?, code) {
+            return Ok(Some(c));
+        }
+    }
+    Ok(None)
+}
+
+/// Check a TOTP code against the time steps within `window` of `time`,
+/// returning the matching step offset (0 means the current step).
+pub fn process_totp_verify(
+    secret: &[u8],
+    code: &str,
+    time: u64,
+    window: u64,
+    params: &TotpParams,
+) -> Result<Option<i64>> {
+    let window = i64::try_from(window)?;
+    for offset in -window..=window {
+        // steps before the epoch or past u64::MAX can't match
+        let step_time = offset
+            .unsigned_abs()
+            .checked_mul(params.period)
+            .and_then(|delta| {
+                if offset < 0 {
+                    time.checked_sub(delta)
+                } else {
+                    time.checked_add(delta)
+                }
+            });
+        let Some(step_time) = step_time else {
+            continue;
+        };
+        if codes_eq(&process_totp(secret, step_time, params)?, code) {
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+/// Generate a random secret of `length` bytes, returning it base32 encoded
+/// together with an `otpauth://totp/` provisioning URI.
+pub fn process_otp_secret(
+    length: usize,
+    issuer: &str,
+    account: &str,
+    params: &TotpParams,
+) -> Result<(String, String)> {
+    if length < 16 {
+        anyhow::bail!("OTP secrets must be at least 16 bytes (128 bits)");
+    }
+    let mut secret = vec![0u8; length];
+    OsRng.fill_bytes(&mut secret);
+    let secret = BASE32_NOPAD.encode(&secret);
+
+    let encode = |s: &str| utf8_percent_encode(s, NON_ALPHANUMERIC).to_string();
+    let label = if issuer.is_empty() {
+        encode(account)
+    } else {
+        format!("{}:{}", encode(issuer), encode(account))
+    };
+    let mut uri = format!(
+        "otpauth://totp/{}?secret={}&algorithm={}&digits={}&period={}",
+        label,
+        secret,
+        params.algorithm.to_string().to_uppercase(),
+        params.digits,
+        params.period
+    );
+    if !issuer.is_empty() {
+        uri.push_str(&format!("&issuer={}", encode(issuer)));
+    }
+    Ok((secret, uri))
+}
+
+pub fn unix_time() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn hmac_digest<M: Mac + hmac::digest::KeyInit>(secret: &[u8], counter: u64) -> Result<Vec<u8>> {
+    let mut mac = <M as Mac>::new_from_slice(secret)?;
+    mac.update(&counter.to_be_bytes());
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn codes_eq(expected: &str, code: &str) -> bool {
+    expected.as_bytes().ct_eq(code.trim().as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET_SHA1: &[u8] = b"12345678901234567890";
+    const RFC_SECRET_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const RFC_SECRET_SHA512: &[u8] =
+        b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn test_hotp_rfc4226_vectors() -> Result<()> {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            let hotp = process_hotp(RFC_SECRET_SHA1, counter as u64, 6, OtpAlgorithm::Sha1)?;
+            assert_eq!(&hotp, code);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_totp_rfc6238_vectors() -> Result<()> {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            for (secret, algorithm, code) in [
+                (RFC_SECRET_SHA1, OtpAlgorithm::Sha1, sha1),
+                (RFC_SECRET_SHA256, OtpAlgorithm::Sha256, sha256),
+                (RFC_SECRET_SHA512, OtpAlgorithm::Sha512, sha512),
+            ] {
+                let params = TotpParams {
+                    digits: 8,
+                    period: 30,
+                    algorithm,
+                };
+                assert_eq!(process_totp(secret, time, &params)?, code);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_otp_verify_window() -> Result<()> {
+        let params = TotpParams::default();
+        let code = process_totp(RFC_SECRET_SHA1, 1000, &params)?;
+        assert_eq!(
+            process_totp_verify(RFC_SECRET_SHA1, &code, 1030, 1, &params)?,
+            Some(-1)
+        );
+        assert_eq!(
+            process_totp_verify(RFC_SECRET_SHA1, &code, 1090, 1, &params)?,
+            None
+        );
+
+        let hotp = process_hotp_verify(RFC_SECRET_SHA1, "969429", 1, 3, 6, OtpAlgorithm::Sha1)?;
+        assert_eq!(hotp, Some(3));
+        let hotp = process_hotp_verify(RFC_SECRET_SHA1, "969429", 4, 3, 6, OtpAlgorithm::Sha1)?;
+        assert_eq!(hotp, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_totp_verify_out_of_range_steps() -> Result<()> {
+        let long_period = TotpParams {
+            period: 1 << 62,
+            ..TotpParams::default()
+        };
+        let code = process_totp(RFC_SECRET_SHA1, 0, &long_period)?;
+        assert_eq!(
+            process_totp_verify(RFC_SECRET_SHA1, &code, 0, 2, &long_period)?,
+            Some(0)
+        );
+
+        let params = TotpParams {
+            period: 3000,
+            ..TotpParams::default()
+        };
+        let time = u64::MAX - 100;
+        let code = process_totp(RFC_SECRET_SHA1, time, &params)?;
+        assert_eq!(
+            process_totp_verify(RFC_SECRET_SHA1, &code, time, 1, &params)?,
+            Some(0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_otp_secret() -> Result<()> {
+        let params = TotpParams::default();
+        let (secret, uri) = process_otp_secret(20, "ACME Co", "alice@example.com", &params)?;
+        assert_eq!(decode_otp_secret(&secret.to_lowercase())?.len(), 20);
+        assert_eq!(
+            uri,
+            format!(
+                "otpauth://totp/ACME%20Co:alice%40example%2Ecom?secret={}&algorithm=SHA1&digits=6&period=30&issuer=ACME%20Co",
+                secret
+            )
+        );
+        assert!(process_otp_secret(8, "", "bob", &params).is_err());
+        assert!(decode_otp_secret("not base32!").is_err());
+        Ok(())
+    }
+}
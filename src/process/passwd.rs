@@ -0,0 +1,250 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::cli::PasswordHashAlgorithm;
+use anyhow::Result;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use serde::Serialize;
+use zxcvbn::zxcvbn;
+
+/// Strength estimate of one candidate password. The password itself is not
+/// kept, candidates are identified by their line number in the input.
+#[derive(Debug, Serialize)]
+pub struct PasswordReport {
+    pub line: usize,
+    pub score: u8,
+    pub guesses: u64,
+    pub guesses_log10: f64,
+    pub crack_times: CrackTimeReport,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+/// Human readable crack-time estimates for the attack scenarios modelled by zxcvbn.
+#[derive(Debug, Serialize)]
+pub struct CrackTimeReport {
+    pub online_throttled: String,
+    pub online_unthrottled: String,
+    pub offline_slow_hash: String,
+    pub offline_fast_hash: String,
+}
+
+/// Cost parameters for password hashing, `None` keeps the algorithm's default.
+///
+/// - `memory`: argon2id memory in KiB
+/// - `iterations`: argon2id passes or pbkdf2 rounds
+/// - `parallelism`: argon2id lanes or scrypt `p`
+/// - `cost`: bcrypt cost or scrypt `log_n`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PasswordHashParams {
+    pub memory: Option<u32>,
+    pub iterations: Option<u32>,
+    pub parallelism: Option<u32>,
+    pub cost: Option<u32>,
+}
+
+/// Estimate the strength of every non-empty line read from `reader`.
+pub fn process_passwd_check(reader: &mut dyn Read) -> Result<Vec<PasswordReport>> {
+    let mut reports = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let password = line.trim_end_matches('\r');
+        if password.is_empty() {
+            continue;
+        }
+        reports.push(check_password(i + 1, password)?);
+    }
+    Ok(reports)
+}
+
+fn check_password(line: usize, password: &str) -> Result<PasswordReport> {
+    let estimate = zxcvbn(password, &[])?;
+    let crack_times = estimate.crack_times();
+    let (warning, suggestions) = match estimate.feedback() {
+        Some(feedback) => (
+            feedback.warning().map(|w| w.to_string()),
+            feedback
+                .suggestions()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    };
+    Ok(PasswordReport {
+        line,
+        score: estimate.score(),
+        guesses: estimate.guesses(),
+        guesses_log10: estimate.guesses_log10(),
+        crack_times: CrackTimeReport {
+            online_throttled: crack_times.online_throttling_100_per_hour().to_string(),
+            online_unthrottled: crack_times.online_no_throttling_10_per_second().to_string(),
+            offline_slow_hash: crack_times
+                .offline_slow_hashing_1e4_per_second()
+                .to_string(),
+            offline_fast_hash: crack_times
+                .offline_fast_hashing_1e10_per_second()
+                .to_string(),
+        },
+        warning,
+        suggestions,
+    })
+}
+
+/// Hash `password` into a PHC string (or modular crypt format for bcrypt).
+pub fn process_passwd_hash(
+    password: &str,
+    algorithm: PasswordHashAlgorithm,
+    params: &PasswordHashParams,
+) -> Result<String> {
+    let password = password.as_bytes();
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = match algorithm {
+        PasswordHashAlgorithm::Argon2id => {
+            let default = argon2::Params::default();
+            let params = argon2::Params::new(
+                params.memory.unwrap_or(default.m_cost()),
+                params.iterations.unwrap_or(default.t_cost()),
+                params.parallelism.unwrap_or(default.p_cost()),
+                None,
+            )?;
+            Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password(password, &salt)?
+                .to_string()
+        }
+        PasswordHashAlgorithm::Bcrypt => {
+            if password.len() > 72 {
+                anyhow::bail!("bcrypt only supports passwords up to 72 bytes");
+            }
+            bcrypt::hash(password, params.cost.unwrap_or(bcrypt::DEFAULT_COST))?
+        }
+        PasswordHashAlgorithm::Scrypt => {
+            let default = scrypt::Params::default();
+            let params = scrypt::Params::new(
+                params
+                    .cost
+                    .map(u8::try_from)
+                    .transpose()?
+                    .unwrap_or(default.log_n()),
+                default.r(),
+                params.parallelism.unwrap_or(default.p()),
+                scrypt::Params::RECOMMENDED_LEN,
+            )?;
+            Scrypt
+                .hash_password_customized(password, None, None, params, &salt)?
+                .to_string()
+        }
+        PasswordHashAlgorithm::Pbkdf2 => {
+            let mut pbkdf2_params = pbkdf2::Params::default();
+            if let Some(rounds) = params.iterations {
+                pbkdf2_params.rounds = rounds;
+            }
+            Pbkdf2
+                .hash_password_customized(password, None, None, pbkdf2_params, &salt)?
+                .to_string()
+        }
+    };
+    Ok(hash)
+}
+
+/// Check `password` against a hash produced by `process_passwd_hash`, the
+/// algorithm and its parameters are taken from the hash string itself.
+pub fn process_passwd_verify(password: &str, hash: &str) -> Result<bool> {
+    let password = password.as_bytes();
+    let hash = hash.trim();
+    if hash.starts_with("$2") {
+        return Ok(bcrypt::verify(password, hash)?);
+    }
+
+    let parsed = PasswordHash::new(hash)?;
+    let result = match parsed.algorithm.as_str() {
+        "argon2id" | "argon2i" | "argon2d" => Argon2::default().verify_password(password, &parsed),
+        "scrypt" => Scrypt.verify_password(password, &parsed),
+        "pbkdf2-sha256" | "pbkdf2-sha512" | "pbkdf2" => Pbkdf2.verify_password(password, &parsed),
+        algorithm => anyhow::bail!("Unsupported password hash algorithm: {}", algorithm),
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_passwd_check() -> Result<()> {
+        let input = b"password\n\nCorrect-Horse-Battery-Staple-42\r\n";
+        let reports = process_passwd_check(&mut &input[..])?;
+        assert_eq!(reports.len(), 2);
+
+        assert_eq!(reports[0].line, 1);
+        assert_eq!(reports[0].score, 0);
+        assert!(reports[0].warning.is_some());
+        assert!(!reports[0].suggestions.is_empty());
+
+        assert_eq!(reports[1].line, 3);
+        assert_eq!(reports[1].score, 4);
+        assert!(reports[1].guesses_log10 > 10.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_passwd_hash_verify() -> Result<()> {
+        // cheap parameters to keep the tests fast
+        let params = [
+            (
+                PasswordHashAlgorithm::Argon2id,
+                PasswordHashParams {
+                    memory: Some(64),
+                    iterations: Some(1),
+                    ..Default::default()
+                },
+                "$argon2id$v=19$m=64,t=1,p=1$",
+            ),
+            (
+                PasswordHashAlgorithm::Bcrypt,
+                PasswordHashParams {
+                    cost: Some(4),
+                    ..Default::default()
+                },
+                "$2b$04$",
+            ),
+            (
+                PasswordHashAlgorithm::Scrypt,
+                PasswordHashParams {
+                    cost: Some(4),
+                    ..Default::default()
+                },
+                "$scrypt$ln=4,r=8,p=1$",
+            ),
+            (
+                PasswordHashAlgorithm::Pbkdf2,
+                PasswordHashParams {
+                    iterations: Some(1000),
+                    ..Default::default()
+                },
+                "$pbkdf2-sha256$i=1000,l=32$",
+            ),
+        ];
+        for (algorithm, params, prefix) in params {
+            let hash = process_passwd_hash("hunter2", algorithm, &params)?;
+            assert!(hash.starts_with(prefix), "{}", hash);
+            assert!(process_passwd_verify("hunter2", &hash)?);
+            assert!(!process_passwd_verify("hunter3", &hash)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_passwd_verify_invalid_hash() {
+        assert!(process_passwd_verify("hunter2", "not a hash").is_err());
+        assert!(process_passwd_verify("hunter2", "$md5$abc$def").is_err());
+    }
+}
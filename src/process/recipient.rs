@@ -0,0 +1,153 @@
+use std::{fs, path::Path};
+
+use super::{error::CryptoError, key_encryption::read_key_file, key_format::sha256_fingerprint};
+use anyhow::Result;
+use chacha20poly1305::{
+    aead::{Aead, OsRng},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+const HKDF_INFO: &[u8] = b"rcli-x25519-v1";
+const TAG_LEN: usize = 16;
+/// Ephemeral public key followed by the wrapped file key and its tag.
+pub(super) const STANZA_LEN: usize = 32 + 32 + TAG_LEN;
+
+/// The file key wrapped for one recipient: an ephemeral X25519 public key and
+/// the file key sealed under a key derived from the ECDH shared secret.
+pub(super) struct Stanza {
+    pub ephemeral: [u8; 32],
+    pub wrapped: [u8; 32 + TAG_LEN],
+}
+
+/// An X25519 private key used to decrypt files sent to its public key.
+pub struct X25519Identity {
+    secret: StaticSecret,
+}
+
+impl X25519Identity {
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        let key: [u8; 32] = key.try_into().map_err(|_| CryptoError::InvalidKeyLength {
+            algorithm: "x25519",
+            expected: 32,
+            actual: key.len(),
+        })?;
+        Ok(Self {
+            secret: StaticSecret::from(key),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    /// `SHA256:` fingerprint of the raw public key.
+    pub fn fingerprint(&self) -> String {
+        sha256_fingerprint(&self.public_key())
+    }
+
+    /// Try to recover the file key from one stanza, `None` if it is not ours.
+    pub(super) fn unwrap(&self, stanza: &Stanza) -> Option<Key> {
+        let ephemeral = PublicKey::from(stanza.ephemeral);
+        let shared = self.secret.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() {
+            return None;
+        }
+        let recipient = PublicKey::from(&self.secret);
+        let cipher = wrap_cipher(shared.as_bytes(), &ephemeral, &recipient);
+        let key = cipher
+            .decrypt(&Nonce::default(), stanza.wrapped.as_ref())
+            .ok()?;
+        Some(*Key::from_slice(&key))
+    }
+}
+
+/// Read an X25519 public key file, 32 raw bytes.
+pub(super) fn load_recipient(path: &str) -> Result<PublicKey> {
+    let key = fs::read(path)?;
+    let key: [u8; 32] = key
+        .as_slice()
+        .try_into()
+        .map_err(|_| CryptoError::InvalidKeyLength {
+            algorithm: "x25519",
+            expected: 32,
+            actual: key.len(),
+        })?;
+    Ok(PublicKey::from(key))
+}
+
+/// Wrap `file_key` for `recipient` with a fresh ephemeral key pair.
+pub(super) fn wrap_file_key(file_key: &Key, recipient: &PublicKey) -> Result<Stanza> {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral = PublicKey::from(&secret);
+    let shared = secret.diffie_hellman(recipient);
+    if !shared.was_contributory() {
+        anyhow::bail!("Invalid x25519 recipient key");
+    }
+    let cipher = wrap_cipher(shared.as_bytes(), &ephemeral, recipient);
+    // every wrap key is used exactly once, so a fixed nonce is safe
+    let wrapped = cipher
+        .encrypt(&Nonce::default(), file_key.as_slice())
+        .map_err(|e| anyhow::anyhow!("Error wrapping file key: {}", e))?;
+    Ok(Stanza {
+        ephemeral: ephemeral.to_bytes(),
+        wrapped: wrapped
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Unexpected wrapped key length"))?,
+    })
+}
+
+/// HKDF-SHA256 over the shared secret, salted with both public keys.
+fn wrap_cipher(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let mut salt = ephemeral.as_bytes().to_vec();
+    salt.extend(recipient.as_bytes());
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(HKDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    ChaCha20Poly1305::new(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap_file_key() -> Result<()> {
+        let alice = X25519Identity::generate();
+        let bob = X25519Identity::generate();
+        let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+
+        let stanza = wrap_file_key(&file_key, &PublicKey::from(alice.public_key()))?;
+        assert_eq!(alice.unwrap(&stanza), Some(file_key));
+        assert_eq!(bob.unwrap(&stanza), None);
+
+        let mut tampered = Stanza {
+            ephemeral: stanza.ephemeral,
+            wrapped: stanza.wrapped,
+        };
+        tampered.wrapped[0] ^= 1;
+        assert_eq!(alice.unwrap(&tampered), None);
+
+        let restored = X25519Identity::try_new(&alice.to_bytes())?;
+        assert_eq!(restored.public_key(), alice.public_key());
+        assert!(X25519Identity::try_new(&[0; 31]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::{Read, Write};
+
+use super::{
+    encrypt::{decrypt_with_header, encrypt_with_header, EncryptionKey},
+    error::CryptoError,
+    text::{Ed25519Signer, Ed25519Verifier, KeyLoader, TextSign, TextVerify},
+};
+use crate::cli::TextCipher;
+use anyhow::Result;
+
+/// Magic of the signed envelope inside the encrypted stream.
+const ENVELOPE_MAGIC: &[u8; 4] = b"RSEL";
+const ENVELOPE_VERSION: u8 = 1;
+/// `magic || version || signer public key || signature`, then the message.
+const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 1 + 32 + 64;
+/// Prepended to the message before signing, so a seal signature can never be
+/// passed off as a plain `text sign` signature of the same message.
+const SIGN_CONTEXT: &[u8] = b"rcli-seal-v1\0";
+
+/// Sign everything from `reader` with the ed25519 key at `signing_key`, then
+/// encrypt the message together with the signature and the signer's public
+/// key as a `process_text_encrypt` stream.
+///
+/// The signature also covers the stream header, which holds the key slot:
+/// a recipient cannot re-encrypt the envelope to someone else and pass it off
+/// as sent to them by the signer.
+pub fn process_text_seal(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    signing_key: &str,
+    key: &EncryptionKey,
+    algorithm: TextCipher,
+    armor: bool,
+) -> Result<()> {
+    let signer = Ed25519Signer::load(signing_key)?;
+    let mut message = Vec::new();
+    reader.read_to_end(&mut message)?;
+
+    encrypt_with_header(writer, key, algorithm, armor, |header| {
+        let sig = signer.sign(&mut signed_data(header, &message))?;
+        let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + message.len());
+        envelope.extend(ENVELOPE_MAGIC);
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend(signer.verifier().to_bytes());
+        envelope.extend(sig);
+        envelope.extend(&message);
+        Ok(envelope)
+    })
+}
+
+/// Decrypt a sealed message and check its signature, returning the signer's
+/// fingerprint. Nothing is written unless the signature is valid and, when
+/// `signer` is given, made by that public key.
+pub fn process_text_open(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    key: &EncryptionKey,
+    signer: Option<&str>,
+) -> Result<String> {
+    let mut envelope = Vec::new();
+    let stream_header = decrypt_with_header(reader, &mut envelope, key)?;
+    if envelope.len() < ENVELOPE_MAGIC.len() || &envelope[..ENVELOPE_MAGIC.len()] != ENVELOPE_MAGIC
+    {
+        return Err(CryptoError::InvalidCiphertext("not a sealed message").into());
+    }
+    if envelope.len() < ENVELOPE_HEADER_LEN {
+        return Err(CryptoError::TruncatedCiphertext.into());
+    }
+    if envelope[ENVELOPE_MAGIC.len()] != ENVELOPE_VERSION {
+        return Err(CryptoError::InvalidCiphertext("unsupported envelope version").into());
+    }
+    let (header, message) = envelope.split_at(ENVELOPE_HEADER_LEN);
+    let (public_key, sig) = header[ENVELOPE_MAGIC.len() + 1..].split_at(32);
+
+    let verifier = Ed25519Verifier::try_new(public_key)?;
+    let fingerprint = verifier.fingerprint();
+    if !verifier.verify(signed_data(&stream_header, message), sig)? {
+        let message = format!("Invalid signature by {}", fingerprint);
+        return Err(CryptoError::VerificationFailed(message).into());
+    }
+    if let Some(signer) = signer {
+        let expected = Ed25519Verifier::load(signer)?;
+        if expected.to_bytes() != verifier.to_bytes() {
+            let message = format!(
+                "Sealed by {}, expected {}",
+                fingerprint,
+                expected.fingerprint()
+            );
+            return Err(CryptoError::VerificationFailed(message).into());
+        }
+    }
+    writer.write_all(message)?;
+    writer.flush()?;
+    Ok(fingerprint)
+}
+
+/// `context || stream header || message`, the header is self-delimiting.
+fn signed_data<'a>(stream_header: &'a [u8], message: &'a [u8]) -> impl Read + 'a {
+    SIGN_CONTEXT.chain(stream_header).chain(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::TextKeyType, process_text_decrypt, process_text_encrypt, process_text_key_generate,
+    };
+
+    #[test]
+    fn test_seal_open() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let alice = process_text_key_generate(
+            TextKeyType::Ed25519,
+            dir.path(),
+            Some("alice"),
+            false,
+            None,
+        )?;
+        process_text_key_generate(
+            TextKeyType::Ed25519,
+            dir.path(),
+            Some("mallory"),
+            false,
+            None,
+        )?;
+        process_text_key_generate(TextKeyType::X25519, dir.path(), Some("bob"), false, None)?;
+        let recipients = [path("bob.pk")];
+        let seal_key = EncryptionKey::Recipients(&recipients);
+        let open_key = EncryptionKey::Identity(&path("bob.sk"));
+
+        let mut sealed = Vec::new();
+        let algorithm = TextCipher::ChaCha20Poly1305;
+        process_text_seal(
+            &mut &b"meet at noon"[..],
+            &mut sealed,
+            &path("alice.sk"),
+            &seal_key,
+            algorithm,
+            true,
+        )?;
+
+        let mut opened = Vec::new();
+        let fingerprint = process_text_open(&mut &sealed[..], &mut opened, &open_key, None)?;
+        assert_eq!(opened, b"meet at noon");
+        assert_eq!(Some(fingerprint), alice.fingerprint);
+        let mut opened = Vec::new();
+        process_text_open(
+            &mut &sealed[..],
+            &mut opened,
+            &open_key,
+            Some(&path("alice.pk")),
+        )?;
+        assert_eq!(opened, b"meet at noon");
+
+        // the wrong expected signer is rejected and nothing is written
+        let mut opened = Vec::new();
+        let err = process_text_open(
+            &mut &sealed[..],
+            &mut opened,
+            &open_key,
+            Some(&path("mallory.pk")),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::VerificationFailed(_))
+        ));
+        assert!(opened.is_empty());
+
+        // a plain encrypted message is not a sealed one
+        let mut encrypted = Vec::new();
+        process_text_encrypt(
+            &mut &b"meet at noon"[..],
+            &mut encrypted,
+            &seal_key,
+            algorithm,
+            false,
+        )?;
+        let err =
+            process_text_open(&mut &encrypted[..], &mut Vec::new(), &open_key, None).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CryptoError>(),
+            Some(&CryptoError::InvalidCiphertext("not a sealed message"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_forged_signature() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_file = dir.path().join("stream.key");
+        std::fs::write(&key_file, [9u8; 32])?;
+        let key = EncryptionKey::KeyFile(key_file.to_str().unwrap());
+
+        // an envelope naming alice's key over a message she never signed
+        let alice = Ed25519Signer::load("fixtures/ed25519.sk")?;
+        let sig = alice.sign(&mut SIGN_CONTEXT.chain(&b"pay bob"[..]))?;
+        let mut envelope = ENVELOPE_MAGIC.to_vec();
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend(alice.verifier().to_bytes());
+        envelope.extend(sig);
+        envelope.extend(b"pay mallory");
+        let mut sealed = Vec::new();
+        process_text_encrypt(
+            &mut &envelope[..],
+            &mut sealed,
+            &key,
+            TextCipher::Aes256Gcm,
+            false,
+        )?;
+
+        let mut opened = Vec::new();
+        let err = process_text_open(&mut &sealed[..], &mut opened, &key, None).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid signature"), "{}", err);
+        assert!(opened.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_reencrypted_envelope() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        for (key_type, name) in [
+            (TextKeyType::Ed25519, "alice"),
+            (TextKeyType::X25519, "bob"),
+            (TextKeyType::X25519, "carol"),
+        ] {
+            process_text_key_generate(key_type, dir.path(), Some(name), false, None)?;
+        }
+        let to_bob = [path("bob.pk")];
+        let to_carol = [path("carol.pk")];
+        let bob = path("bob.sk");
+        let carol = path("carol.sk");
+
+        let mut sealed = Vec::new();
+        process_text_seal(
+            &mut &b"you are hired"[..],
+            &mut sealed,
+            &path("alice.sk"),
+            &EncryptionKey::Recipients(&to_bob),
+            TextCipher::ChaCha20Poly1305,
+            false,
+        )?;
+
+        // bob decrypts alice's envelope and forwards it unchanged to carol
+        let mut envelope = Vec::new();
+        process_text_decrypt(
+            &mut &sealed[..],
+            &mut envelope,
+            &EncryptionKey::Identity(&bob),
+        )?;
+        let mut forwarded = Vec::new();
+        process_text_encrypt(
+            &mut &envelope[..],
+            &mut forwarded,
+            &EncryptionKey::Recipients(&to_carol),
+            TextCipher::ChaCha20Poly1305,
+            false,
+        )?;
+
+        let mut opened = Vec::new();
+        let err = process_text_open(
+            &mut &forwarded[..],
+            &mut opened,
+            &EncryptionKey::Identity(&carol),
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid signature"), "{}", err);
+        assert!(opened.is_empty());
+        Ok(())
+    }
+}
@@ -1,13 +1,38 @@
-use std::{fs, io::Read, path::Path};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
 
-use crate::{cli::TextSignFormat, get_reader, process_genpass};
+use super::{
+    ecdsa_sign::{P256Signer, P256Verifier, Secp256k1Signer, Secp256k1Verifier},
+    error::CryptoError,
+    key_encryption::{encrypt_key, read_key_file},
+    key_format::{ed25519_fingerprint, Ed25519Key},
+    recipient::X25519Identity,
+};
+use crate::{
+    cli::{TextKeyType, TextSignFormat},
+    get_reader,
+};
 use anyhow::Result;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, OsRng},
+    aead::{rand_core::RngCore, Aead, AeadCore, OsRng, Payload},
     ChaCha20Poly1305, Key, KeyInit, Nonce,
 };
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+/// A 96-bit nonce followed by a 128-bit Poly1305 tag.
+const CHACHA20POLY1305_SIG_LEN: usize = 12 + 16;
+
+/// Shortest HMAC key accepted, shorter keys are too easy to brute force.
+const HMAC_MIN_KEY_LEN: usize = 32;
+
+/// Prefix of versioned blake3 key files, followed by the base64url encoded key.
+const BLAKE3_KEY_PREFIX: &str = "rcli:blake3:v1:";
 
 pub trait TextSign {
     /// Sign the data from the reader and return the signature.
@@ -33,6 +58,16 @@ pub struct Blake3 {
     key: [u8; 32],
 }
 
+/// HMAC-SHA256 (HS256) keyed with the raw bytes of the key file.
+pub struct HmacSha256 {
+    key: Vec<u8>,
+}
+
+/// HMAC-SHA512 (HS512) keyed with the raw bytes of the key file.
+pub struct HmacSha512 {
+    key: Vec<u8>,
+}
+
 pub struct Ed25519Signer {
     key: SigningKey,
 }
@@ -56,7 +91,14 @@ pub fn process_text_sign(input: &str, key: &str, format: TextSignFormat) -> Resu
             let signer = Ed25519Signer::load(key)?;
             signer.sign(&mut reader)?
         }
-        TextSignFormat::ChaCha20Poly1305 => todo!(),
+        TextSignFormat::ChaCha20Poly1305 => {
+            let signer = ChaCha20Poly1305Cipher::load(key)?;
+            signer.sign(&mut reader)?
+        }
+        TextSignFormat::P256 => P256Signer::load(key)?.sign(&mut reader)?,
+        TextSignFormat::Secp256k1 => Secp256k1Signer::load(key)?.sign(&mut reader)?,
+        TextSignFormat::HmacSha256 => HmacSha256::load(key)?.sign(&mut reader)?,
+        TextSignFormat::HmacSha512 => HmacSha512::load(key)?.sign(&mut reader)?,
     };
     let signed = URL_SAFE_NO_PAD.encode(signed);
     Ok(signed)
@@ -69,7 +111,9 @@ pub fn process_text_verify(
     sig: &str,
 ) -> Result<bool> {
     let reader = get_reader(input)?;
-    let sig = URL_SAFE_NO_PAD.decode(sig)?;
+    let sig = URL_SAFE_NO_PAD
+        .decode(sig)
+        .map_err(|_| CryptoError::MalformedSignature(format.into()))?;
     let verified = match format {
         TextSignFormat::Blake3 => {
             let verifier = Blake3::load(key)?;
@@ -79,102 +123,280 @@ pub fn process_text_verify(
             let verifier = Ed25519Verifier::load(key)?;
             verifier.verify(reader, &sig)?
         }
-        TextSignFormat::ChaCha20Poly1305 => todo!(),
+        TextSignFormat::ChaCha20Poly1305 => {
+            let verifier = ChaCha20Poly1305Cipher::load(key)?;
+            verifier.verify(reader, &sig)?
+        }
+        TextSignFormat::P256 => P256Verifier::load(key)?.verify(reader, &sig)?,
+        TextSignFormat::Secp256k1 => Secp256k1Verifier::load(key)?.verify(reader, &sig)?,
+        TextSignFormat::HmacSha256 => HmacSha256::load(key)?.verify(reader, &sig)?,
+        TextSignFormat::HmacSha512 => HmacSha512::load(key)?.verify(reader, &sig)?,
     };
     Ok(verified)
 }
 
-pub fn process_text_generate(format: TextSignFormat) -> Result<Vec<Vec<u8>>> {
-    match format {
-        TextSignFormat::Blake3 => Blake3::generate(),
-        TextSignFormat::Ed25519 => Ed25519Signer::generate(),
-        TextSignFormat::ChaCha20Poly1305 => ChaCha20Poly1305Cipher::generate(),
+pub fn process_text_generate(key_type: TextKeyType) -> Result<Vec<Vec<u8>>> {
+    match key_type {
+        TextKeyType::Blake3 => Blake3::generate(),
+        TextKeyType::Ed25519 => Ed25519Signer::generate(),
+        TextKeyType::P256 => P256Signer::generate(),
+        TextKeyType::Secp256k1 => Secp256k1Signer::generate(),
+        TextKeyType::HmacSha256 => HmacSha256::generate(),
+        TextKeyType::HmacSha512 => HmacSha512::generate(),
+        // every stream cipher takes a random 256-bit key
+        TextKeyType::ChaCha20Poly1305 | TextKeyType::XChaCha20Poly1305 | TextKeyType::Aes256Gcm => {
+            ChaCha20Poly1305Cipher::generate()
+        }
+        TextKeyType::X25519 => {
+            let identity = X25519Identity::generate();
+            Ok(vec![
+                identity.to_bytes().to_vec(),
+                identity.public_key().to_vec(),
+            ])
+        }
     }
 }
 
-pub fn process_text_encrypt(input: &str, key: &str) -> Result<String> {
-    let cipher = ChaCha20Poly1305Cipher::load(key)?;
+/// Key files written by `process_text_key_generate`.
+#[derive(Debug)]
+pub struct GeneratedKey {
+    pub files: Vec<PathBuf>,
+    /// SHA256 fingerprint of the public key, for asymmetric formats only.
+    pub fingerprint: Option<String>,
+}
 
-    let mut reader = get_reader(input)?;
-    let mut buf = Vec::new();
-    // TODO: improve perf by reading in chunks.
-    reader.read_to_end(&mut buf)?;
-
-    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); // 96-bits; unique per message
-    let result = cipher.cipher.encrypt(&nonce, buf.as_ref());
-    match result {
-        Ok(encrypted) => {
-            let mut v = Vec::new();
-            v.extend(nonce);
-            v.extend(encrypted);
-            let ciphertext = URL_SAFE_NO_PAD.encode(v);
-            Ok(ciphertext)
+/// Generate a key and write it into `dir` (created if missing) as `<name>.key`,
+/// or `<name>.sk` and `<name>.pk` for key pairs. `name` defaults to the key type.
+/// Existing files are only replaced with `force`, private keys are written
+/// with `0600` permissions and encrypted when a `passphrase` is given.
+pub fn process_text_key_generate(
+    key_type: TextKeyType,
+    dir: &Path,
+    name: Option<&str>,
+    force: bool,
+    passphrase: Option<&str>,
+) -> Result<GeneratedKey> {
+    let name = name
+        .map(String::from)
+        .unwrap_or_else(|| key_type.to_string());
+    let keys = process_text_generate(key_type)?;
+    let files = match key_type {
+        TextKeyType::Ed25519 | TextKeyType::P256 | TextKeyType::Secp256k1 | TextKeyType::X25519 => {
+            vec![
+                dir.join(format!("{}.sk", name)),
+                dir.join(format!("{}.pk", name)),
+            ]
+        }
+        _ => vec![dir.join(format!("{}.key", name))],
+    };
+    if !force {
+        if let Some(existing) = files.iter().find(|f| f.exists()) {
+            anyhow::bail!(
+                "{} already exists, use --force to overwrite it",
+                existing.display()
+            );
         }
-        Err(e) => anyhow::bail!("Error encrypting: {}", e),
     }
-}
 
-pub fn process_text_decrypt(input: &str, key: &str) -> Result<String> {
-    let cipher = ChaCha20Poly1305Cipher::load(key)?;
-    let mut reader = get_reader(input)?;
-    let mut s = String::new();
-    // TODO: improve perf by reading in chunks.
-    reader.read_to_string(&mut s)?;
-
-    let buf = URL_SAFE_NO_PAD.decode(s.trim())?;
-
-    let nonce = &buf[..12];
-    let ciphertext = &buf[12..];
-    let result = cipher
-        .cipher
-        .decrypt(Nonce::from_slice(nonce), ciphertext.as_ref());
-    let decrypted = match result {
-        Ok(decrypted) => String::from_utf8(decrypted)?,
-        Err(e) => anyhow::bail!(e.to_string()),
+    let fingerprint = match key_type {
+        TextKeyType::Ed25519 => Some(Ed25519Verifier::try_new(&keys[1])?.fingerprint()),
+        TextKeyType::P256 => Some(P256Verifier::try_new(&keys[1])?.fingerprint()?),
+        TextKeyType::Secp256k1 => Some(Secp256k1Verifier::try_new(&keys[1])?.fingerprint()?),
+        TextKeyType::X25519 => Some(X25519Identity::try_new(&keys[0])?.fingerprint()),
+        _ => None,
     };
-    Ok(decrypted)
+    fs::create_dir_all(dir)?;
+    for (i, (file, key)) in files.iter().zip(keys.iter()).enumerate() {
+        // only the public key of a pair is not secret
+        let private = !(files.len() == 2 && i == 1);
+        match passphrase {
+            Some(passphrase) if private => {
+                let encrypted = encrypt_key(key, passphrase, argon2::Params::default())?;
+                write_key_file(file, &encrypted, private, force)?
+            }
+            _ => write_key_file(file, key, private, force)?,
+        }
+    }
+    Ok(GeneratedKey { files, fingerprint })
+}
+
+/// Write a key to `path`, private keys with `0600` permissions. Existing files
+/// are only replaced with `force`.
+pub fn write_key_file(path: &Path, key: &[u8], private: bool, force: bool) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mode = if private { 0o600 } else { 0o644 };
+        options.mode(mode);
+        let mut file = options.open(path)?;
+        // the mode only applies to new files, tighten overwritten ones too
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        file.write_all(key)?;
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = private;
+        options.open(path)?.write_all(key)?;
+    }
+    Ok(())
 }
 
 impl TextSign for Blake3 {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        // TODO: improve perf by reading in chunks.
-        reader.read_to_end(&mut buf)?;
-        let key = blake3::keyed_hash(&self.key, &buf);
-        Ok(key.as_bytes().to_vec())
+        let hash = self.keyed_hash(reader)?;
+        Ok(hash.as_bytes().to_vec())
     }
 }
 
 impl TextVerify for Blake3 {
+    /// `blake3::Hash` equality is constant-time, so the comparison does not
+    /// leak how many leading bytes of a forged MAC were right.
     fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let hash = blake3::keyed_hash(&self.key, &buf);
-        let hash = hash.as_bytes();
-        Ok(hash == sig)
+        check_signature_length("blake3", blake3::OUT_LEN, sig)?;
+        let sig: [u8; blake3::OUT_LEN] = sig.try_into()?;
+        let hash = self.keyed_hash(&mut reader)?;
+        Ok(hash == blake3::Hash::from(sig))
+    }
+}
+
+impl TextSign for HmacSha256 {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
+        let mac = hmac_stream::<Hmac<Sha256>>(&self.key, reader)?;
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+impl TextVerify for HmacSha256 {
+    fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        check_signature_length("hmac-sha256", 32, sig)?;
+        let mac = hmac_stream::<Hmac<Sha256>>(&self.key, &mut reader)?;
+        Ok(mac.verify_slice(sig).is_ok())
+    }
+}
+
+impl TextSign for HmacSha512 {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
+        let mac = hmac_stream::<Hmac<Sha512>>(&self.key, reader)?;
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+impl TextVerify for HmacSha512 {
+    fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        check_signature_length("hmac-sha512", 64, sig)?;
+        let mac = hmac_stream::<Hmac<Sha512>>(&self.key, &mut reader)?;
+        Ok(mac.verify_slice(sig).is_ok())
+    }
+}
+
+/// Stream the reader through a fresh MAC, comparing tags is left to
+/// `Mac::verify_slice`, which runs in constant time.
+fn hmac_stream<M: Mac + KeyInit + Write>(key: &[u8], reader: &mut dyn Read) -> Result<M> {
+    let mut mac = <M as Mac>::new_from_slice(key)?;
+    io::copy(reader, &mut mac)?;
+    Ok(mac)
+}
+
+fn check_signature_length(algorithm: &'static str, expected: usize, sig: &[u8]) -> Result<()> {
+    if sig.len() != expected {
+        return Err(CryptoError::InvalidSignatureLength {
+            algorithm,
+            expected,
+            actual: sig.len(),
+        }
+        .into());
     }
+    Ok(())
 }
+
+/// Ed25519ph (RFC 8032): the input is streamed through SHA-512 and the digest
+/// is signed, so arbitrarily large inputs are signed in constant memory.
 impl TextSign for Ed25519Signer {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let sig = self.key.sign(&buf);
+        let digest = prehash::<Sha512>(reader)?;
+        let sig = self.key.sign_prehashed(digest, None)?;
         Ok(sig.to_bytes().to_vec())
     }
 }
 
 impl TextVerify for Ed25519Verifier {
     fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        let sig: &[u8; 64] = sig
+            .try_into()
+            .map_err(|_| CryptoError::InvalidSignatureLength {
+                algorithm: "ed25519",
+                expected: 64,
+                actual: sig.len(),
+            })?;
+        let sig = Signature::from_bytes(sig);
+        let digest = prehash::<Sha512>(&mut reader)?;
+        Ok(self.key.verify_prehashed(digest, None, &sig).is_ok())
+    }
+}
+
+/// Stream everything from `reader` into a new `D` digest.
+pub(super) fn prehash<D: Digest + Write>(reader: &mut dyn Read) -> Result<D> {
+    let mut digest = D::new();
+    io::copy(reader, &mut digest)?;
+    Ok(digest)
+}
+
+/// ChaCha20Poly1305 used as a MAC: the data is authenticated as associated data
+/// of an empty message, so the tag is a Poly1305 one-time MAC keyed from the
+/// ChaCha20 keystream of a random nonce. The signature is `nonce || tag`.
+impl TextSign for ChaCha20Poly1305Cipher {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
-        let sig = Signature::from_bytes(sig.try_into()?);
-        Ok(self.key.verify(&buf, &sig).is_ok())
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &[],
+            aad: &buf,
+        };
+        let tag = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|e| anyhow::anyhow!("Error signing: {}", e))?;
+        let mut sig = nonce.to_vec();
+        sig.extend(tag);
+        Ok(sig)
+    }
+}
+
+impl TextVerify for ChaCha20Poly1305Cipher {
+    fn verify<R: Read>(&self, mut reader: R, sig: &[u8]) -> Result<bool> {
+        if sig.len() != CHACHA20POLY1305_SIG_LEN {
+            return Err(CryptoError::InvalidSignatureLength {
+                algorithm: "chacha20poly1305",
+                expected: CHACHA20POLY1305_SIG_LEN,
+                actual: sig.len(),
+            }
+            .into());
+        }
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let (nonce, tag) = sig.split_at(12);
+        let payload = Payload {
+            msg: tag,
+            aad: &buf,
+        };
+        Ok(self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .is_ok())
     }
 }
 
 impl KeyLoader for Blake3 {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let key = fs::read(path)?;
+        let key = read_key_file(path)?;
         Self::try_new(&key)
     }
 }
@@ -184,11 +406,74 @@ impl Blake3 {
         Self { key }
     }
 
+    /// Accepts versioned `rcli:blake3:v1:` keys as well as legacy keys whose
+    /// first 32 bytes are the raw key, e.g. the printable keys older versions generated.
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
-        let key = key.try_into()?;
-        let signer = Blake3::new(key);
-        Ok(signer)
+        if let Some(encoded) = key.strip_prefix(BLAKE3_KEY_PREFIX.as_bytes()) {
+            let decoded = URL_SAFE_NO_PAD.decode(encoded.trim_ascii())?;
+            let key = decoded
+                .as_slice()
+                .try_into()
+                .map_err(|_| invalid_key_length("blake3", 32, decoded.len()))?;
+            return Ok(Blake3::new(key));
+        }
+        match key.get(..32) {
+            Some(key) => Ok(Blake3::new(key.try_into()?)),
+            None => Err(invalid_key_length("blake3", 32, key.len()).into()),
+        }
+    }
+
+    /// Stream the reader through blake3 in keyed mode.
+    fn keyed_hash(&self, reader: &mut dyn Read) -> Result<blake3::Hash> {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        io::copy(reader, &mut hasher)?;
+        Ok(hasher.finalize())
+    }
+
+    /// Encode the key in the versioned key file format.
+    pub fn encode_key(&self) -> String {
+        format!(
+            "{}{}\n",
+            BLAKE3_KEY_PREFIX,
+            URL_SAFE_NO_PAD.encode(self.key)
+        )
+    }
+}
+
+impl HmacSha256 {
+    /// Any key of at least 32 bytes, longer keys are hashed as HMAC specifies.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            key: hmac_key("hmac-sha256", key)?,
+        })
+    }
+}
+
+impl HmacSha512 {
+    /// Any key of at least 32 bytes, longer keys are hashed as HMAC specifies.
+    pub fn try_new(key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            key: hmac_key("hmac-sha512", key)?,
+        })
+    }
+}
+
+fn hmac_key(algorithm: &'static str, key: &[u8]) -> Result<Vec<u8>> {
+    if key.len() < HMAC_MIN_KEY_LEN {
+        return Err(invalid_key_length(algorithm, HMAC_MIN_KEY_LEN, key.len()).into());
+    }
+    Ok(key.to_vec())
+}
+
+impl KeyLoader for HmacSha256 {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
+    }
+}
+
+impl KeyLoader for HmacSha512 {
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::try_new(&read_key_file(path)?)
     }
 }
 
@@ -197,16 +482,21 @@ impl Ed25519Signer {
         Self { key }
     }
 
+    /// Accepts raw 32 byte seeds, PKCS#8 PEM, OpenSSH and JWK private keys.
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = SigningKey::from_bytes(key.try_into()?);
+        let key = Ed25519Key::decode(key, false)?.into_signing_key()?;
         let signer = Ed25519Signer::new(key);
         Ok(signer)
     }
+
+    pub fn verifier(&self) -> Ed25519Verifier {
+        Ed25519Verifier::new(self.key.verifying_key())
+    }
 }
 
 impl KeyLoader for Ed25519Signer {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let key = fs::read(path)?;
+        let key = read_key_file(path)?;
         Self::try_new(&key)
     }
 }
@@ -216,23 +506,35 @@ impl Ed25519Verifier {
         Self { key }
     }
 
+    /// Accepts raw 32 byte public keys, SPKI PEM, `ssh-ed25519` and JWK public
+    /// keys, as well as any private key format the public key is derived from.
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = VerifyingKey::from_bytes(key.try_into()?)?;
+        let key = Ed25519Key::decode(key, true)?.verifying_key();
         let verifier = Ed25519Verifier::new(key);
         Ok(verifier)
     }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key.to_bytes()
+    }
+
+    /// The OpenSSH style `SHA256:` fingerprint of the public key.
+    pub fn fingerprint(&self) -> String {
+        ed25519_fingerprint(&self.key)
+    }
 }
 impl KeyLoader for Ed25519Verifier {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let key = fs::read(path)?;
+        let key = read_key_file(path)?;
         Self::try_new(&key)
     }
 }
 
 impl KeyGenerator for Blake3 {
     fn generate() -> Result<Vec<Vec<u8>>> {
-        let key = process_genpass(32, true, true, true, true)?;
-        let key = key.as_bytes().to_vec();
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let key = Blake3::new(key).encode_key().into_bytes();
         Ok(vec![key])
     }
 }
@@ -247,6 +549,23 @@ impl KeyGenerator for Ed25519Signer {
     }
 }
 
+/// Keys as long as the hash output, the length RFC 2104 recommends.
+impl KeyGenerator for HmacSha256 {
+    fn generate() -> Result<Vec<Vec<u8>>> {
+        let mut key = vec![0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Ok(vec![key])
+    }
+}
+
+impl KeyGenerator for HmacSha512 {
+    fn generate() -> Result<Vec<Vec<u8>>> {
+        let mut key = vec![0u8; 64];
+        OsRng.fill_bytes(&mut key);
+        Ok(vec![key])
+    }
+}
+
 impl KeyGenerator for ChaCha20Poly1305Cipher {
     fn generate() -> Result<Vec<Vec<u8>>> {
         let key = ChaCha20Poly1305::generate_key(&mut OsRng);
@@ -256,7 +575,7 @@ impl KeyGenerator for ChaCha20Poly1305Cipher {
 
 impl KeyLoader for ChaCha20Poly1305Cipher {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let key = fs::read(path)?;
+        let key = read_key_file(path)?;
         Self::try_new(&key)
     }
 }
@@ -268,14 +587,155 @@ impl ChaCha20Poly1305Cipher {
     }
 
     pub fn try_new(key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            return Err(invalid_key_length("chacha20poly1305", 32, key.len()).into());
+        }
         let key = Key::clone_from_slice(key);
         Ok(ChaCha20Poly1305Cipher::new(key))
     }
 }
 
+pub(super) fn invalid_key_length(
+    algorithm: &'static str,
+    expected: usize,
+    actual: usize,
+) -> CryptoError {
+    CryptoError::InvalidKeyLength {
+        algorithm,
+        expected,
+        actual,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::process::{fuzz_rng, key_format::sha256_fingerprint};
+    use rand::Rng;
+
+    #[test]
+    fn test_key_and_signature_fuzz() -> Result<()> {
+        let mut rng = fuzz_rng();
+        let blake3 = Blake3::load("fixtures/blake3.txt")?;
+        let pk = Ed25519Verifier::load("fixtures/ed25519.pk")?;
+        for _ in 0..500 {
+            let len = rng.gen_range(0..80);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            // none of these may panic, whatever the input
+            let _ = Blake3::try_new(&bytes);
+            let _ = Ed25519Signer::try_new(&bytes);
+            let _ = Ed25519Verifier::try_new(&bytes);
+            let chacha = ChaCha20Poly1305Cipher::try_new(&bytes);
+            assert_eq!(chacha.is_ok(), len == 32);
+            let _ = blake3.verify(&b"hello"[..], &bytes);
+            let _ = pk.verify(&b"hello"[..], &bytes);
+        }
+
+        for (len, algorithm) in [(0, "blake3"), (31, "blake3")] {
+            let err = Blake3::try_new(&vec![b'a'; len]).err().unwrap();
+            assert_eq!(
+                err.downcast_ref::<CryptoError>(),
+                Some(&CryptoError::InvalidKeyLength {
+                    algorithm,
+                    expected: 32,
+                    actual: len
+                })
+            );
+        }
+        let err = ChaCha20Poly1305Cipher::try_new(&[0; 33]).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidKeyLength { actual: 33, .. })
+        ));
+        let err = pk.verify(&b"hello"[..], &[0; 63]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidSignatureLength { actual: 63, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_length_signatures_rejected() -> Result<()> {
+        let blake3 = Blake3::load("fixtures/blake3.txt")?;
+        let hmac256 = HmacSha256::try_new(&[7; 32])?;
+        let hmac512 = HmacSha512::try_new(&[7; 64])?;
+        let chacha = ChaCha20Poly1305Cipher::try_new(&[7; 32])?;
+        let sig = blake3.sign(&mut &b"hello"[..])?;
+        assert!(blake3.verify(&b"hello"[..], &sig)?);
+
+        for len in [0, 1, 31, 33, 63, 65, 128] {
+            let sig = vec![0u8; len];
+            let results = [
+                (32, blake3.verify(&b"hello"[..], &sig)),
+                (32, hmac256.verify(&b"hello"[..], &sig)),
+                (64, hmac512.verify(&b"hello"[..], &sig)),
+                (28, chacha.verify(&b"hello"[..], &sig)),
+            ];
+            for (expected, result) in results {
+                if len == expected {
+                    assert!(!result?);
+                    continue;
+                }
+                let err = result.unwrap_err();
+                assert!(
+                    matches!(
+                        err.downcast_ref::<CryptoError>(),
+                        Some(CryptoError::InvalidSignatureLength { actual, .. }) if *actual == len
+                    ),
+                    "{}",
+                    err
+                );
+            }
+        }
+        // a valid MAC cut short or extended is a length error, not a mismatch
+        assert!(blake3.verify(&b"hello"[..], &sig[..31]).is_err());
+        let mut long = sig.clone();
+        long.push(0);
+        assert!(blake3.verify(&b"hello"[..], &long).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_text_key_generate() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("keys");
+        let generated =
+            process_text_key_generate(TextKeyType::Ed25519, &out, Some("deploy"), false, None)?;
+        assert_eq!(
+            generated.files,
+            [out.join("deploy.sk"), out.join("deploy.pk")]
+        );
+        let fingerprint = generated.fingerprint.unwrap();
+        assert!(fingerprint.starts_with("SHA256:"), "{}", fingerprint);
+
+        let sk = fs::read(out.join("deploy.sk"))?;
+        let err =
+            process_text_key_generate(TextKeyType::Ed25519, &out, Some("deploy"), false, None)
+                .unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert_eq!(fs::read(out.join("deploy.sk"))?, sk);
+        process_text_key_generate(TextKeyType::Ed25519, &out, Some("deploy"), true, None)?;
+        assert_ne!(fs::read(out.join("deploy.sk"))?, sk);
+
+        let generated = process_text_key_generate(TextKeyType::Blake3, &out, None, false, None)?;
+        assert_eq!(generated.files, [out.join("blake3.key")]);
+        assert!(generated.fingerprint.is_none());
+
+        let generated = process_text_key_generate(TextKeyType::X25519, &out, None, false, None)?;
+        let public_key = fs::read(out.join("x25519.pk"))?;
+        assert_eq!(generated.fingerprint, Some(sha256_fingerprint(&public_key)));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |f: &str| fs::metadata(out.join(f)).map(|m| m.permissions().mode() & 0o777);
+            assert_eq!(mode("deploy.sk")?, 0o600);
+            assert_eq!(mode("deploy.pk")?, 0o644);
+            assert_eq!(mode("blake3.key")?, 0o600);
+        }
+        Ok(())
+    }
 
     #[test]
     fn take_blake3_sign_verify() -> Result<()> {
@@ -286,6 +746,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_blake3_generated_key() -> Result<()> {
+        let key = Blake3::generate()?.remove(0);
+        assert!(key.starts_with(BLAKE3_KEY_PREFIX.as_bytes()));
+        let blake3 = Blake3::try_new(&key)?;
+        assert_eq!(blake3.encode_key().into_bytes(), key);
+
+        let other = Blake3::try_new(&Blake3::generate()?.remove(0))?;
+        assert_ne!(blake3.key, other.key);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blake3_legacy_key() -> Result<()> {
+        let legacy = fs::read("fixtures/blake3.key")?;
+        let blake3 = Blake3::load("fixtures/blake3.key")?;
+        assert_eq!(&blake3.key[..], &legacy[..32]);
+        assert!(Blake3::try_new(b"too short").is_err());
+        assert!(Blake3::try_new(b"rcli:blake3:v1:AAAA").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blake3_streaming_matches_keyed_hash() -> Result<()> {
+        let blake3 = Blake3::load("fixtures/blake3.txt")?;
+        // larger than any internal buffer, so the input is hashed in many chunks
+        let data = vec![42u8; 3 * 1024 * 1024 + 7];
+        let sig = blake3.sign(&mut &data[..])?;
+        assert_eq!(sig, blake3::keyed_hash(&blake3.key, &data).as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ed25519_streaming_large_input() -> Result<()> {
+        let sk = Ed25519Signer::load("fixtures/ed25519.sk")?;
+        let pk = Ed25519Verifier::load("fixtures/ed25519.pk")?;
+        let mut reader = io::repeat(7).take(4 * 1024 * 1024);
+        let sig = sk.sign(&mut reader)?;
+        assert!(pk.verify(io::repeat(7).take(4 * 1024 * 1024), &sig)?);
+        assert!(!pk.verify(io::repeat(7).take(4 * 1024 * 1024 - 1), &sig)?);
+        Ok(())
+    }
+
     #[test]
     fn test_ed25519_sign_verify() -> Result<()> {
         let sk = Ed25519Signer::load("fixtures/ed25519.sk")?;
@@ -297,6 +800,113 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_chacha20poly1305_sign_verify() -> Result<()> {
+        let key = ChaCha20Poly1305Cipher::generate()?.remove(0);
+        let cipher = ChaCha20Poly1305Cipher::try_new(&key)?;
+        let data = b"hello";
+        let sig = cipher.sign(&mut &data[..])?;
+        assert_eq!(sig.len(), CHACHA20POLY1305_SIG_LEN);
+        assert!(cipher.verify(&data[..], &sig)?);
+        assert!(!cipher.verify(&b"hellO"[..], &sig)?);
+        assert!(cipher.verify(&data[..], &sig[1..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_text_sign_verify_all_formats() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let chacha_key = dir.path().join("chacha20poly1305.key");
+        fs::write(&chacha_key, &ChaCha20Poly1305Cipher::generate()?[0])?;
+        let chacha_key = chacha_key.to_str().unwrap();
+
+        for (format, sk, pk) in [
+            (
+                TextSignFormat::Blake3,
+                "fixtures/blake3.txt",
+                "fixtures/blake3.txt",
+            ),
+            (
+                TextSignFormat::Ed25519,
+                "fixtures/ed25519.sk",
+                "fixtures/ed25519.pk",
+            ),
+            (TextSignFormat::ChaCha20Poly1305, chacha_key, chacha_key),
+        ] {
+            let sig = process_text_sign("Cargo.toml", sk, format)?;
+            assert!(process_text_verify("Cargo.toml", pk, format, &sig)?);
+            assert!(!process_text_verify("fixtures/b64.txt", pk, format, &sig)?);
+            let err = process_text_verify("Cargo.toml", pk, format, "!!!").unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<CryptoError>(),
+                Some(&CryptoError::MalformedSignature(format.into()))
+            );
+        }
+
+        for format in [
+            TextSignFormat::P256,
+            TextSignFormat::Secp256k1,
+            TextSignFormat::HmacSha256,
+            TextSignFormat::HmacSha512,
+        ] {
+            let generated =
+                process_text_key_generate(format.into(), dir.path(), None, false, None)?;
+            let asymmetric = matches!(format, TextSignFormat::P256 | TextSignFormat::Secp256k1);
+            assert_eq!(generated.fingerprint.is_some(), asymmetric, "{}", format);
+            let files = generated.files;
+            let sk = files[0].to_str().unwrap();
+            let pk = files.last().unwrap().to_str().unwrap();
+            let sig = process_text_sign("Cargo.toml", sk, format)?;
+            assert!(process_text_verify("Cargo.toml", pk, format, &sig)?);
+            assert!(!process_text_verify("fixtures/b64.txt", pk, format, &sig)?);
+            let err = process_text_verify("Cargo.toml", pk, format, "!!!").unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<CryptoError>(),
+                Some(&CryptoError::MalformedSignature(format.into()))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_rfc4231_vectors() -> Result<()> {
+        // RFC 4231 test case 6, a key longer than the block size
+        let key = [0xaa; 131];
+        let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        let hex = |sig: &[u8]| sig.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        let sha256 = HmacSha256::try_new(&key)?;
+        let sig = sha256.sign(&mut &data[..])?;
+        assert_eq!(
+            hex(&sig),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert!(sha256.verify(&data[..], &sig)?);
+        let sha512 = HmacSha512::try_new(&key)?;
+        let sig512 = sha512.sign(&mut &data[..])?;
+        assert_eq!(
+            hex(&sig512),
+            concat!(
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352",
+                "6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+            )
+        );
+        assert!(sha512.verify(&data[..], &sig512)?);
+
+        assert!(!sha256.verify(&b"other"[..], &sig)?);
+        let err = sha512.verify(&data[..], &sig).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidSignatureLength { actual: 32, .. })
+        ));
+        let err = HmacSha256::try_new(b"short").err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CryptoError>(),
+            Some(CryptoError::InvalidKeyLength { actual: 5, .. })
+        ));
+        Ok(())
+    }
+
     #[test]
     fn test_chacha20poly1305() -> Result<(), chacha20poly1305::Error> {
         let key = ChaCha20Poly1305::generate_key(&mut OsRng);
@@ -1,5 +1,10 @@
-use anyhow::Result;
-use std::{fs::File, io::Read};
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tracing::warn;
 
 pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -9,3 +14,107 @@ pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     };
     Ok(reader)
 }
+
+pub fn get_writer(output: &str) -> Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    Ok(writer)
+}
+
+/// Write to `output` through `f`. Files are written to a temp file next to
+/// `output` and only renamed into place once `f` succeeds, so a failure never
+/// leaves partial output. The output may be plaintext, so on unix it is
+/// created 0600.
+pub fn with_output<F, T>(output: &str, f: F) -> Result<T>
+where
+    F: FnOnce(&mut dyn Write) -> Result<T>,
+{
+    if output == "-" {
+        let mut writer = BufWriter::new(std::io::stdout());
+        let value = f(&mut writer)?;
+        writer.flush()?;
+        return Ok(value);
+    }
+    let dir = match Path::new(output).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // removed on drop if `f` fails, and created 0600 on unix
+    let tmp = tempfile::Builder::new()
+        .prefix(".rcli-")
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    let mut writer = BufWriter::new(tmp.as_file());
+    let value = f(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    tmp.persist(output).map_err(|e| e.error)?;
+    Ok(value)
+}
+
+/// Recursively collect all regular files below `dir`, following symlinks.
+/// A symlink back to one of its own parent directories is not descended into.
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut ancestors = Vec::new();
+    collect_files_inner(dir, files, &mut ancestors)
+}
+
+fn collect_files_inner(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+    ancestors: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let canonical = dir.canonicalize()?;
+    if ancestors.contains(&canonical) {
+        warn!("Skipping symlink loop at {:?}", dir);
+        return Ok(());
+    }
+    ancestors.push(canonical);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = match entry.file_type()? {
+            t if t.is_symlink() => fs::metadata(&path)
+                .with_context(|| format!("Broken symlink {}", path.display()))?
+                .file_type(),
+            t => t,
+        };
+        if file_type.is_dir() {
+            collect_files_inner(&path, files, ancestors)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_output_keeps_unrelated_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("plain.txt");
+        let neighbour = dir.path().join("plain.txt.tmp");
+        fs::write(&neighbour, b"keep me")?;
+
+        let output = output.to_str().unwrap();
+        with_output(output, |writer| Ok(writer.write_all(b"hello")?))?;
+        assert_eq!(fs::read(output)?, b"hello");
+        assert_eq!(fs::read(&neighbour)?, b"keep me");
+
+        let failed: Result<()> = with_output(output, |writer| {
+            writer.write_all(b"partial")?;
+            anyhow::bail!("failed")
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(output)?, b"hello");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+        Ok(())
+    }
+}